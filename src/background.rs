@@ -0,0 +1,174 @@
+//! Models of what a dataset's background looks like. The background model
+//! decides which pixels are background when deriving silhouettes and when
+//! checking the consistency of a voxel, so datasets shot on white or green
+//! backdrops can be carved as well as the black Middlebury ones.
+use image::DynamicImage;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+/// How to tell background pixels apart from the object, set by the
+/// `background` field of a dataset description, e.g.
+/// `"background": { "model": "solid", "color": [1, 1, 1], "tolerance": 0.1 }`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "kebab-case")]
+pub enum Background {
+    /// every pixel within `tolerance` of `color` (in every channel) is
+    /// background
+    Solid {
+        color: [f32; 3],
+        #[serde(default = "default_tolerance")]
+        tolerance: f32,
+    },
+    /// every pixel whose hue is within `hue_tolerance` degrees of `hue` and
+    /// that is saturated and bright enough to be the backdrop is background,
+    /// e.g. a green screen
+    ChromaKey {
+        #[serde(default = "default_hue")]
+        hue: f32,
+        #[serde(default = "default_hue_tolerance")]
+        hue_tolerance: f32,
+        #[serde(default = "default_min_saturation")]
+        min_saturation: f32,
+        #[serde(default = "default_min_value")]
+        min_value: f32,
+    },
+    /// each image `<name>.png` has a photo of the empty scene
+    /// `<name><suffix>.png` taken from the same camera. Every pixel within
+    /// `tolerance` of the plate (in every channel) is background.
+    Plates {
+        suffix: String,
+        #[serde(default = "default_tolerance")]
+        tolerance: f32,
+    },
+}
+
+fn default_tolerance() -> f32 {
+    0.05
+}
+fn default_hue() -> f32 {
+    120.0
+}
+fn default_hue_tolerance() -> f32 {
+    30.0
+}
+fn default_min_saturation() -> f32 {
+    0.3
+}
+fn default_min_value() -> f32 {
+    0.2
+}
+
+/// exactly black, which is what the Middlebury datasets use
+impl Default for Background {
+    fn default() -> Self {
+        Background::Solid {
+            color: [0.0, 0.0, 0.0],
+            tolerance: 0.0,
+        }
+    }
+}
+
+/// convert a color with channels in `[0, 1]` to `(hue in degrees, saturation,
+/// value)`
+fn to_hsv(color: &glm::Vec3) -> (f32, f32, f32) {
+    let max = color.x.max(color.y).max(color.z);
+    let min = color.x.min(color.y).min(color.z);
+    let chroma = max - min;
+
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == color.x {
+        60.0 * ((color.y - color.z) / chroma).rem_euclid(6.0)
+    } else if max == color.y {
+        60.0 * ((color.z - color.x) / chroma + 2.0)
+    } else {
+        60.0 * ((color.x - color.y) / chroma + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+
+    (hue, saturation, max)
+}
+
+/// convert a fully saturated, full brightness hue in degrees to a color
+fn from_hue(hue: f32) -> glm::Vec3 {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    match h as u32 {
+        0 => glm::vec3(1.0, x, 0.0),
+        1 => glm::vec3(x, 1.0, 0.0),
+        2 => glm::vec3(0.0, 1.0, x),
+        3 => glm::vec3(0.0, x, 1.0),
+        4 => glm::vec3(x, 0.0, 1.0),
+        _ => glm::vec3(1.0, 0.0, x),
+    }
+}
+
+/// the color of the pixel at `(x, y)` of `img` with channels in `[0, 1]`
+fn pixel(img: &image::RgbImage, x: u32, y: u32) -> glm::Vec3 {
+    let pix = img.get_pixel(x, y).0;
+    glm::vec3(
+        pix[0] as f32 / 255.0,
+        pix[1] as f32 / 255.0,
+        pix[2] as f32 / 255.0,
+    )
+}
+
+impl Background {
+    /// the suffix of the background plate images, if this model uses them
+    pub fn plate_suffix(&self) -> Option<&str> {
+        match self {
+            Background::Plates { suffix, .. } => Some(suffix),
+            _ => None,
+        }
+    }
+    /// the color the background is expected to have, for models that don't
+    /// use plates. Chroma keys give their fully saturated key color.
+    pub fn color(&self) -> glm::Vec3 {
+        match self {
+            Background::Solid { color, .. } => glm::make_vec3(color),
+            Background::ChromaKey { hue, .. } => from_hue(*hue),
+            Background::Plates { .. } => glm::vec3(0.0, 0.0, 0.0),
+        }
+    }
+    /// true if `color` is background. `plate` is the color of the background
+    /// plate at the same pixel, and is ignored by models that don't use plates.
+    pub fn matches(&self, color: &glm::Vec3, plate: &glm::Vec3) -> bool {
+        match self {
+            Background::Solid { tolerance, .. } => (color - self.color()).abs().max() <= *tolerance,
+            Background::ChromaKey {
+                hue,
+                hue_tolerance,
+                min_saturation,
+                min_value,
+            } => {
+                let (h, s, v) = to_hsv(color);
+                let distance = (h - hue).rem_euclid(360.0);
+                distance.min(360.0 - distance) <= *hue_tolerance
+                    && s >= *min_saturation
+                    && v >= *min_value
+            }
+            Background::Plates { tolerance, .. } => (color - plate).abs().max() <= *tolerance,
+        }
+    }
+    /// a foreground mask for `img`, where `plate` is its background plate if
+    /// this model uses them
+    pub fn silhouette(&self, img: &DynamicImage, plate: Option<&DynamicImage>) -> Vec<Vec<bool>> {
+        let img = img.to_rgb8();
+        let plate = plate.map(|plate| plate.to_rgb8());
+        let color = self.color();
+
+        (0..img.height())
+            .map(|y| {
+                (0..img.width())
+                    .map(|x| {
+                        let plate = match &plate {
+                            Some(plate) => pixel(plate, x, y),
+                            None => color,
+                        };
+                        !self.matches(&pixel(&img, x, y), &plate)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,132 @@
+//! Bounding box estimation. Only points that every view can see can be
+//! reconstructed, so the bounding box of a dataset is the bounding box of the
+//! intersection of all the view frusta. If the views have silhouettes, it can
+//! be tightened further to the bounding box of the visual hull.
+//!
+//! Neither of these has a convenient closed form, so both are found by
+//! sampling a grid of points over a box, shrinking the box to the points that
+//! pass and repeating at the finer spacing this gives.
+use crate::error::{CarvingError, Result};
+use crate::view::View;
+use image::GenericImageView;
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+
+/// the number of samples along each axis of the box
+const RESOLUTION: usize = 64;
+/// the most times the box is shrunk for each test
+const MAX_ITERATIONS: usize = 8;
+
+/// the pixel `point` projects to in `view`, if it's inside the image
+fn pixel(view: &View, point: &glm::Vec3) -> Option<(u32, u32)> {
+    let p = view.camera.project(point)?;
+    let (x, y) = (p.x.floor(), p.y.floor());
+    if x < 0.0 || y < 0.0 || x >= view.img.width() as f32 || y >= view.img.height() as f32 {
+        return None;
+    }
+    Some((x as u32, y as u32))
+}
+
+/// true if `point` is inside the frustum of `view`
+fn in_frustum(view: &View, point: &glm::Vec3) -> bool {
+    pixel(view, point).is_some()
+}
+
+/// true if `point` projects inside the silhouette of `view`
+fn in_silhouette(view: &View, point: &glm::Vec3) -> bool {
+    pixel(view, point).is_some_and(|(x, y)| view.in_silhouette(x, y))
+}
+
+/// Shrink the box from `min` to `max` to the samples that pass `test` in
+/// every view, grown by one sample spacing so that nothing between the
+/// samples is lost. Returns `None` if no sample passes.
+fn shrink(
+    views: &[View],
+    mut min: glm::Vec3,
+    mut max: glm::Vec3,
+    test: fn(&View, &glm::Vec3) -> bool,
+) -> Option<(glm::Vec3, glm::Vec3)> {
+    for _ in 0..MAX_ITERATIONS {
+        let step = (max - min) / RESOLUTION as f32;
+
+        let bounds = (0..RESOLUTION)
+            .into_par_iter()
+            .flat_map(|z| {
+                (0..RESOLUTION).into_par_iter().flat_map(move |y| {
+                    (0..RESOLUTION).into_par_iter().map(move |x| {
+                        min + glm::vec3(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5)
+                            .component_mul(&step)
+                    })
+                })
+            })
+            .filter(|point| views.iter().all(|view| test(view, point)))
+            .map(|point| Some((point, point)))
+            .reduce(
+                || None,
+                |a, b| match (a, b) {
+                    (Some(a), Some(b)) => Some((glm::min2(&a.0, &b.0), glm::max2(&a.1, &b.1))),
+                    (a, None) => a,
+                    (None, b) => b,
+                },
+            );
+        let (low, high) = bounds?;
+
+        // The box never grows, and once it stops shrinking noticeably there's
+        // nothing left to gain
+        let low = glm::max2(&(low - step), &min);
+        let high = glm::min2(&(high + step), &max);
+        let shrunk = (max - min) - (high - low);
+        min = low;
+        max = high;
+        if shrunk.max() < 2.0 * step.max() {
+            break;
+        }
+    }
+
+    Some((min, max))
+}
+
+/// Estimate a tight bounding box for the scene seen by `views`, returned as
+/// `(front_top_left, back_bottom_right)` like `Config::bounding_box`. The
+/// search starts from the box around the sphere through the furthest camera,
+/// centered between the cameras, so the intersection of the frusta is clipped
+/// to it if the views don't all face inwards. Views without a silhouette only
+/// contribute their frustum.
+pub fn estimate(views: &[View]) -> Result<(glm::Vec3, glm::Vec3)> {
+    if views.len() < 2 {
+        return Err(CarvingError::InvalidBoundingBox(
+            "at least two views are needed to estimate it".to_string(),
+        ));
+    }
+
+    let centers: Vec<glm::Vec3> = views.iter().map(|v| v.camera.center()).collect();
+    let middle = centers
+        .iter()
+        .fold(glm::vec3(0.0, 0.0, 0.0), |acc, c| acc + c)
+        / centers.len() as f32;
+    let radius = centers
+        .iter()
+        .map(|c| glm::distance(c, &middle))
+        .fold(0.0, f32::max);
+    let reach = glm::vec3(radius, radius, radius);
+
+    let (min, max) =
+        shrink(views, middle - reach, middle + reach, in_frustum).ok_or_else(|| {
+            CarvingError::InvalidBoundingBox("no point is seen by every view".to_string())
+        })?;
+
+    let (min, max) = if views.iter().any(|v| v.silhouette.is_some()) {
+        shrink(views, min, max, in_silhouette).ok_or_else(|| {
+            CarvingError::InvalidBoundingBox(
+                "no point is inside the silhouette of every view".to_string(),
+            )
+        })?
+    } else {
+        (min, max)
+    };
+
+    Ok((
+        glm::vec3(min.x, max.y, max.z),
+        glm::vec3(max.x, min.y, min.z),
+    ))
+}
@@ -0,0 +1,54 @@
+use super::{average_color, samples_by_view, ConsistencyCheck, Sample};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::Color;
+
+/// consistency checking by color histogram overlap. Each view's samples are
+/// binned into a normalized joint RGB histogram with `bins` bins per channel,
+/// and every pair of views is compared by the overlap (histogram intersection)
+/// of their histograms, which is 1 for identical distributions and 0 for
+/// disjoint ones. Unlike the variance test, this accepts textured elements as
+/// long as every view sees the same mix of colors. A set of views are deemed to
+/// be consistent if the mean overlap is at least `threshold`.
+pub struct Histogram {
+    pub threshold: f32,
+    pub bins: usize,
+}
+impl Histogram {
+    fn histogram(&self, samples: &[&Sample]) -> Vec<f32> {
+        let mut histogram = vec![0.0; self.bins * self.bins * self.bins];
+        let bin = |c: f32| ((c * self.bins as f32) as usize).min(self.bins - 1);
+        for sample in samples {
+            let c = sample.color;
+            histogram[bin(c.x) + self.bins * (bin(c.y) + self.bins * bin(c.z))] += 1.0;
+        }
+        for count in &mut histogram {
+            *count /= samples.len() as f32;
+        }
+        histogram
+    }
+}
+impl ConsistencyCheck for Histogram {
+    fn check(&self, samples: &[Sample], _views: &[&View]) -> Result<Option<Color>> {
+        let histograms: Vec<Vec<f32>> = samples_by_view(samples)
+            .iter()
+            .map(|samples| self.histogram(samples))
+            .collect();
+
+        let mut total_overlap = 0.0;
+        let mut pairs = 0;
+        for (i, a) in histograms.iter().enumerate() {
+            for b in &histograms[i + 1..] {
+                total_overlap += a.iter().zip(b).map(|(a, b)| a.min(*b)).sum::<f32>();
+                pairs += 1;
+            }
+        }
+
+        // A single view has nothing to disagree with
+        if pairs == 0 || total_overlap / pairs as f32 >= self.threshold {
+            Ok(Some(Color::from_vec3(average_color(samples))))
+        } else {
+            Ok(None)
+        }
+    }
+}
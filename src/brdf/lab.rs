@@ -0,0 +1,58 @@
+use super::{ConsistencyCheck, Sample};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::Color;
+use nalgebra_glm as glm;
+
+/// convert an sRGB color with channels in `[0, 1]` to CIELAB (D65 white point)
+fn to_lab(color: &glm::Vec3) -> glm::Vec3 {
+    // undo the sRGB gamma curve
+    let linear = color.map(|c| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+
+    // to XYZ, relative to the D65 white point
+    let x = (0.4124 * linear.x + 0.3576 * linear.y + 0.1805 * linear.z) / 0.95047;
+    let y = 0.2126 * linear.x + 0.7152 * linear.y + 0.0722 * linear.z;
+    let z = (0.0193 * linear.x + 0.1192 * linear.y + 0.9505 * linear.z) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    glm::vec3(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+/// consistency checking by perceptual color difference. Colors are converted
+/// to CIELAB, where euclidean distance (ΔE*ab) roughly matches how different
+/// two colors look, so differences in dark or saturated regions count as much
+/// as elsewhere. A set of views are deemed to be consistent if the root mean
+/// square ΔE of their colors from the mean color is below `threshold`. A ΔE
+/// of about 2.3 is just noticeable.
+pub struct DeltaE {
+    pub threshold: f32,
+}
+impl ConsistencyCheck for DeltaE {
+    fn check(&self, samples: &[Sample], _views: &[&View]) -> Result<Option<Color>> {
+        let labs: Vec<glm::Vec3> = samples.iter().map(|s| to_lab(&s.color)).collect();
+        let mean = labs.iter().fold(glm::vec3(0.0, 0.0, 0.0), |acc, c| acc + c) / labs.len() as f32;
+
+        let mean_squared_difference =
+            labs.iter().map(|c| glm::distance2(c, &mean)).sum::<f32>() / labs.len() as f32;
+
+        if mean_squared_difference.sqrt() < self.threshold {
+            Ok(Some(Color::from_vec3(super::average_color(samples))))
+        } else {
+            Ok(None)
+        }
+    }
+}
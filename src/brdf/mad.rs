@@ -0,0 +1,34 @@
+use super::{median, ConsistencyCheck, Sample};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::Color;
+use nalgebra_glm as glm;
+
+/// consistency checking by median absolute deviation. The median and MAD are
+/// robust versions of the mean and standard deviation: a few outlying pixels
+/// (e.g. a highlight in one view, or a pixel from a neighboring surface) barely
+/// move them. The MAD is scaled by 1.4826 so that it estimates the standard
+/// deviation for normally distributed colors. A set of views are deemed to be
+/// consistent if the scaled MAD is below `threshold` in every channel, and the
+/// element is given the median color.
+pub struct Mad {
+    pub threshold: f32,
+}
+impl ConsistencyCheck for Mad {
+    fn check(&self, samples: &[Sample], _views: &[&View]) -> Result<Option<Color>> {
+        let mut medians = glm::vec3(0.0, 0.0, 0.0);
+
+        for channel in 0..3 {
+            let mut values: Vec<f32> = samples.iter().map(|s| s.color[channel]).collect();
+            let middle = median(&mut values);
+
+            let mut deviations: Vec<f32> = values.iter().map(|v| (v - middle).abs()).collect();
+            if 1.4826 * median(&mut deviations) >= self.threshold {
+                return Ok(None);
+            }
+            medians[channel] = middle;
+        }
+
+        Ok(Some(Color::from_vec3(medians)))
+    }
+}
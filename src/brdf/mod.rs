@@ -0,0 +1,199 @@
+//! this module contains different methods for performing a consistency check
+//! for different views of the same location in a scene to see if the pixel
+//! colors reported by the different views are consistent and therefore if
+//! that location is actually part of the scene volume.
+//!
+//! Every method implements `ConsistencyCheck`, and `Measure` names them so
+//! one can be picked per dataset from the command line or the dataset config.
+use crate::error::{CarvingError, Result};
+use crate::view::View;
+use crate::volume::Color;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+mod histogram;
+mod lab;
+mod mad;
+mod ncc;
+mod robust;
+mod specular;
+mod variance;
+
+pub use histogram::Histogram;
+pub use lab::DeltaE;
+pub use mad::Mad;
+pub use ncc::Ncc;
+pub use robust::{Robust, ViewRejections};
+pub use specular::{Light, Specular};
+pub use variance::Variance;
+
+/// A single pixel that a scene element projects to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub color: glm::Vec3,
+    /// true if the view's background model says this pixel is background
+    pub background: bool,
+    /// the color the background has at this pixel
+    pub background_color: glm::Vec3,
+    /// the vector from the scene element to the camera that saw this pixel
+    pub ray: glm::Vec3,
+    /// the index of the view this pixel belongs to, in the `views` passed
+    /// alongside the samples
+    pub view: usize,
+    /// the coordinates of the pixel in its view
+    pub pixel: (u32, u32),
+}
+
+/// A photo-consistency measure. Given the samples that every view sees of a
+/// scene element, decides whether they agree, and if so what color the
+/// element is. `views` holds the views the samples came from, for measures
+/// that need more than the sampled pixels.
+pub trait ConsistencyCheck: Sync {
+    /// compare the samples. Samples that show background have already been
+    /// ruled out by the time this is called (see `consistent`), and there is
+    /// always at least one sample.
+    fn check(&self, samples: &[Sample], views: &[&View]) -> Result<Option<Color>>;
+
+    /// check the samples, carving the scene element if any view sees
+    /// background there. This is the entry point every carving mode goes
+    /// through.
+    fn consistent(&self, samples: &[Sample], views: &[&View]) -> Result<Option<Color>> {
+        if samples.is_empty() {
+            return Err(CarvingError::NoSamples);
+        }
+
+        // If any camera sees a background pixel then this scene element cannot
+        // possibly exist
+        if samples.iter().any(|s| s.background) {
+            return Ok(None);
+        }
+
+        // Similar to above, an average color close to the average background
+        // color indicates that every view is seeing background, and this
+        // element should be carved.
+        let average_background = samples
+            .iter()
+            .fold(glm::vec3(0.0, 0.0, 0.0), |acc, s| acc + s.background_color)
+            / samples.len() as f32;
+        if (average_color(samples) - average_background).abs().max() < 0.2 {
+            return Ok(None);
+        }
+
+        self.check(samples, views)
+    }
+}
+
+/// perform consistency checking via the voxel coloring algorithm with the
+/// per-channel standard deviation test (see `Variance`)
+pub fn standard_consistency_check(samples: &[Sample], threshold: f32) -> Result<Option<Color>> {
+    Variance { threshold }.consistent(samples, &[])
+}
+
+/// the mean color of the samples
+pub(crate) fn average_color(samples: &[Sample]) -> glm::Vec3 {
+    samples
+        .iter()
+        .fold(glm::vec3(0.0, 0.0, 0.0), |acc, s| acc + s.color)
+        / samples.len() as f32
+}
+
+/// the median of `values`, which must not be empty. Reorders `values`.
+pub(crate) fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// the samples grouped by the view they came from, skipping views without any
+pub(crate) fn samples_by_view(samples: &[Sample]) -> Vec<Vec<&Sample>> {
+    let num_views = samples.iter().map(|s| s.view + 1).max().unwrap_or(0);
+    let mut by_view = vec![vec![]; num_views];
+    for sample in samples {
+        by_view[sample.view].push(sample);
+    }
+    by_view.retain(|samples| !samples.is_empty());
+    by_view
+}
+
+/// The available consistency measures
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Measure {
+    /// per-channel RGB standard deviation (`Variance`)
+    Variance,
+    /// CIELAB color difference (`DeltaE`)
+    DeltaE,
+    /// normalized cross-correlation of small patches (`Ncc`)
+    Ncc,
+    /// color histogram overlap (`Histogram`)
+    Histogram,
+    /// median absolute deviation (`Mad`)
+    Mad,
+    /// diffuse plus specular reflectance fit (`Specular`)
+    Specular,
+}
+impl Measure {
+    /// a threshold that works reasonably for this measure on the Middlebury
+    /// datasets
+    pub fn default_threshold(&self) -> f32 {
+        match self {
+            Measure::Variance => 0.4,
+            Measure::DeltaE => 40.0,
+            Measure::Ncc => 0.0,
+            Measure::Histogram => 0.1,
+            Measure::Mad => 0.4,
+            Measure::Specular => 0.4,
+        }
+    }
+    /// create the consistency check for this measure with the given threshold.
+    /// `light` is the dataset's light, which only `Specular` uses.
+    pub fn build(&self, threshold: f32, light: Option<Light>) -> Box<dyn ConsistencyCheck> {
+        match self {
+            Measure::Variance => Box::new(Variance { threshold }),
+            Measure::DeltaE => Box::new(DeltaE { threshold }),
+            Measure::Ncc => Box::new(Ncc {
+                threshold,
+                radius: 2,
+            }),
+            Measure::Histogram => Box::new(Histogram { threshold, bins: 4 }),
+            Measure::Mad => Box::new(Mad { threshold }),
+            Measure::Specular => Box::new(Specular { threshold, light }),
+        }
+    }
+}
+impl FromStr for Measure {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "variance" => Ok(Measure::Variance),
+            "delta-e" => Ok(Measure::DeltaE),
+            "ncc" => Ok(Measure::Ncc),
+            "histogram" => Ok(Measure::Histogram),
+            "mad" => Ok(Measure::Mad),
+            "specular" => Ok(Measure::Specular),
+            _ => Err(format!(
+                "unknown consistency measure `{}`, expected one of: variance, delta-e, ncc, histogram, mad, specular",
+                s
+            )),
+        }
+    }
+}
+impl fmt::Display for Measure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Measure::Variance => write!(f, "variance"),
+            Measure::DeltaE => write!(f, "delta-e"),
+            Measure::Ncc => write!(f, "ncc"),
+            Measure::Histogram => write!(f, "histogram"),
+            Measure::Mad => write!(f, "mad"),
+            Measure::Specular => write!(f, "specular"),
+        }
+    }
+}
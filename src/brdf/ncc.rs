@@ -0,0 +1,107 @@
+use super::{average_color, samples_by_view, ConsistencyCheck, Sample};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::Color;
+use image::GenericImageView;
+
+/// the standard deviation below which a patch counts as flat
+const FLAT: f32 = 0.02;
+
+/// consistency checking by normalized cross-correlation. For each view, a
+/// square patch of luminance with the given `radius` is taken around the
+/// sampled pixel nearest the middle of the element's footprint, and every pair
+/// of views is compared by the correlation of their patches. NCC ignores
+/// differences in brightness and contrast between views, so it tolerates
+/// exposure changes and soft shading, but it needs texture to work with. Two
+/// flat patches match if their mean luminance is close. A set of views are
+/// deemed to be consistent if the mean correlation is at least `threshold`.
+pub struct Ncc {
+    pub threshold: f32,
+    pub radius: u32,
+}
+impl Ncc {
+    /// the luminance patch centered on the middle of a view's samples,
+    /// clamped to the image
+    fn patch(&self, view: &View, samples: &[&Sample]) -> Vec<f32> {
+        let mean = samples.iter().fold((0.0, 0.0), |(x, y), s| {
+            (x + s.pixel.0 as f32, y + s.pixel.1 as f32)
+        });
+        let mean = (mean.0 / samples.len() as f32, mean.1 / samples.len() as f32);
+        let center = samples
+            .iter()
+            .map(|s| s.pixel)
+            .min_by(|a, b| {
+                let distance =
+                    |p: &(u32, u32)| (p.0 as f32 - mean.0).powi(2) + (p.1 as f32 - mean.1).powi(2);
+                distance(a).total_cmp(&distance(b))
+            })
+            .unwrap_or_default();
+
+        let (width, height) = view.img.dimensions();
+        let r = self.radius as i64;
+        let mut patch = vec![];
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let x = (center.0 as i64 + dx).clamp(0, width as i64 - 1) as u32;
+                let y = (center.1 as i64 + dy).clamp(0, height as i64 - 1) as u32;
+                let c = view.color_at(x, y);
+                patch.push(0.299 * c.x + 0.587 * c.y + 0.114 * c.z);
+            }
+        }
+        patch
+    }
+}
+
+/// the mean and standard deviation of `values`
+fn mean_and_deviation(values: &[f32]) -> (f32, f32) {
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32;
+    (mean, variance.sqrt())
+}
+
+/// the normalized cross-correlation of two patches of the same size
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let (mean_a, deviation_a) = mean_and_deviation(a);
+    let (mean_b, deviation_b) = mean_and_deviation(b);
+
+    if deviation_a < FLAT || deviation_b < FLAT {
+        return if deviation_a < FLAT && deviation_b < FLAT && (mean_a - mean_b).abs() < 0.1 {
+            1.0
+        } else {
+            0.0
+        };
+    }
+
+    let covariance = a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| (a - mean_a) * (b - mean_b))
+        .sum::<f32>()
+        / a.len() as f32;
+    covariance / (deviation_a * deviation_b)
+}
+
+impl ConsistencyCheck for Ncc {
+    fn check(&self, samples: &[Sample], views: &[&View]) -> Result<Option<Color>> {
+        let patches: Vec<Vec<f32>> = samples_by_view(samples)
+            .iter()
+            .map(|samples| self.patch(views[samples[0].view], samples))
+            .collect();
+
+        let mut total_correlation = 0.0;
+        let mut pairs = 0;
+        for (i, a) in patches.iter().enumerate() {
+            for b in &patches[i + 1..] {
+                total_correlation += correlation(a, b);
+                pairs += 1;
+            }
+        }
+
+        // A single view has nothing to disagree with
+        if pairs == 0 || total_correlation / pairs as f32 >= self.threshold {
+            Ok(Some(Color::from_vec3(average_color(samples))))
+        } else {
+            Ok(None)
+        }
+    }
+}
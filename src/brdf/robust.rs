@@ -0,0 +1,140 @@
+use super::{median, samples_by_view, ConsistencyCheck, Sample};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::Color;
+use nalgebra_glm as glm;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// How often a view took part in robust consistency checks, and how often it
+/// was rejected as an outlier
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ViewRejections {
+    pub checked: usize,
+    pub rejected: usize,
+}
+
+/// outlier-rejecting consistency checking that accepts a scene element if
+/// enough of the views that see it agree, as judged by another measure. A
+/// single view with a highlight, motion blur or a calibration error then no
+/// longer gets a real surface carved.
+///
+/// Like every other check, an element is carved as soon as any view sees
+/// background there, so silhouettes and the background model still carve
+/// however many views agree. Only disagreement about the element's color is
+/// put down to outliers: views are ranked by how far their mean color is from
+/// the median of all the views' mean colors, and the check first tries every
+/// view, then drops the furthest views one at a time until
+/// `inner` accepts the remaining views or fewer than `min_inliers` views (or
+/// `min_inlier_ratio` of the views) would be left. Elements seen by fewer than
+/// `min_inliers` views need every view to agree.
+///
+/// Views that are dropped from an accepted check are counted as rejected (see
+/// `rejections`), so that bad images can be spotted. If no subset is accepted,
+/// the element is carved and none of its views are counted as rejected.
+pub struct Robust {
+    pub inner: Box<dyn ConsistencyCheck>,
+    pub min_inliers: usize,
+    pub min_inlier_ratio: f32,
+    rejections: Mutex<BTreeMap<String, ViewRejections>>,
+}
+impl Robust {
+    pub fn new(
+        inner: Box<dyn ConsistencyCheck>,
+        min_inliers: usize,
+        min_inlier_ratio: f32,
+    ) -> Self {
+        Robust {
+            inner,
+            min_inliers,
+            min_inlier_ratio,
+            rejections: Mutex::new(BTreeMap::new()),
+        }
+    }
+    /// how often each view (by name) took part in a check and was rejected
+    pub fn rejections(&self) -> BTreeMap<String, ViewRejections> {
+        self.rejections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+    fn record(&self, views: &[&View], by_view: &[Vec<&Sample>], inliers: usize) {
+        // Samples checked without their views can't be put down to a view
+        if views.is_empty() {
+            return;
+        }
+
+        let mut rejections = self
+            .rejections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (i, samples) in by_view.iter().enumerate() {
+            let entry = rejections
+                .entry(views[samples[0].view].name.clone())
+                .or_default();
+            entry.checked += 1;
+            if i >= inliers {
+                entry.rejected += 1;
+            }
+        }
+    }
+}
+impl ConsistencyCheck for Robust {
+    fn check(&self, samples: &[Sample], views: &[&View]) -> Result<Option<Color>> {
+        self.consistent(samples, views)
+    }
+    fn consistent(&self, samples: &[Sample], views: &[&View]) -> Result<Option<Color>> {
+        // A view that sees background rules the element out, rather than
+        // being dropped as an outlier
+        if samples.iter().any(|s| s.background) {
+            return Ok(None);
+        }
+        let mut by_view = samples_by_view(samples);
+        if by_view.is_empty() {
+            return self.inner.consistent(samples, views);
+        }
+
+        // Rank the views by how far they are from the consensus color
+        let means: Vec<glm::Vec3> = by_view
+            .iter()
+            .map(|samples| {
+                samples
+                    .iter()
+                    .fold(glm::vec3(0.0, 0.0, 0.0), |acc, s| acc + s.color)
+                    / samples.len() as f32
+            })
+            .collect();
+        let mut consensus = glm::vec3(0.0, 0.0, 0.0);
+        for channel in 0..3 {
+            consensus[channel] = median(&mut means.iter().map(|m| m[channel]).collect::<Vec<_>>());
+        }
+        let mut ranked: Vec<(f32, Vec<&Sample>)> = by_view
+            .drain(..)
+            .zip(&means)
+            .map(|(samples, mean)| (glm::distance(mean, &consensus), samples))
+            .collect();
+        ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let by_view: Vec<Vec<&Sample>> = ranked.into_iter().map(|(_, samples)| samples).collect();
+
+        let n = by_view.len();
+        let required = self
+            .min_inliers
+            .max((self.min_inlier_ratio * n as f32).ceil() as usize)
+            .min(n)
+            .max(1);
+
+        for inliers in (required..=n).rev() {
+            let subset: Vec<Sample> = by_view[..inliers]
+                .iter()
+                .flat_map(|samples| samples.iter().map(|&&s| s))
+                .collect();
+            if let Some(color) = self.inner.consistent(&subset, views)? {
+                self.record(views, &by_view, inliers);
+                return Ok(Some(color));
+            }
+        }
+
+        self.record(views, &by_view, n);
+        Ok(None)
+    }
+}
@@ -0,0 +1,114 @@
+use super::{ConsistencyCheck, Sample};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::Color;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+/// The light illuminating a dataset, set by the `light` field of a dataset
+/// description, e.g. `"light": { "direction": [0, 1, 0], "shininess": 20 }`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    /// the direction from the scene towards a distant light
+    pub direction: [f32; 3],
+    /// the exponent of the specular lobe. Higher values give smaller, sharper
+    /// highlights.
+    #[serde(default = "default_shininess")]
+    pub shininess: f32,
+}
+
+fn default_shininess() -> f32 {
+    20.0
+}
+
+/// consistency checking with a non-lambertian reflectance model. Each channel
+/// of each sample is modelled as `diffuse + specular * lobe`, where the lobe is
+/// the Blinn-Phong term `max(0, n . h)^shininess` for the half vector `h`
+/// between the light and the sample's viewing ray. The surface normal `n` isn't
+/// known, so it's taken to be the mean viewing direction, since the views that
+/// see an element are mostly in front of it. Without a `light`, each camera is
+/// assumed to carry its own light, so `h` is just the viewing ray.
+///
+/// `diffuse` and a non-negative `specular` are fitted to the samples by least
+/// squares. A set of views are deemed to be consistent if the root mean square
+/// residual of the fit is below `threshold` in every channel, so a highlight
+/// that a few views see no longer gets a glossy element carved. The element is
+/// given the diffuse color.
+pub struct Specular {
+    pub threshold: f32,
+    pub light: Option<Light>,
+}
+impl Specular {
+    /// the specular lobe of every sample
+    fn lobes(&self, samples: &[Sample]) -> Vec<f32> {
+        let rays: Vec<glm::Vec3> = samples.iter().map(|s| glm::normalize(&s.ray)).collect();
+        let sum = rays.iter().fold(glm::vec3(0.0, 0.0, 0.0), |acc, r| acc + r);
+        // Views on exactly opposite sides cancel out, any of them will do then
+        let normal = if glm::length(&sum) > 1e-6 {
+            glm::normalize(&sum)
+        } else {
+            rays[0]
+        };
+
+        let (light, shininess) = match &self.light {
+            Some(light) => (
+                Some(glm::normalize(&glm::make_vec3(&light.direction))),
+                light.shininess,
+            ),
+            None => (None, default_shininess()),
+        };
+
+        rays.iter()
+            .map(|ray| {
+                let half = match light {
+                    Some(light) => glm::normalize(&(light + ray)),
+                    None => *ray,
+                };
+                glm::dot(&normal, &half).max(0.0).powf(shininess)
+            })
+            .collect()
+    }
+}
+impl ConsistencyCheck for Specular {
+    fn check(&self, samples: &[Sample], _views: &[&View]) -> Result<Option<Color>> {
+        let lobes = self.lobes(samples);
+        let n = samples.len() as f32;
+
+        let mean_lobe = lobes.iter().sum::<f32>() / n;
+        let lobe_variance = lobes.iter().map(|l| (l - mean_lobe).powi(2)).sum::<f32>() / n;
+
+        let mut diffuse = glm::vec3(0.0, 0.0, 0.0);
+        for channel in 0..3 {
+            let values: Vec<f32> = samples.iter().map(|s| s.color[channel]).collect();
+            let mean = values.iter().sum::<f32>() / n;
+
+            // Fit the line `value = diffuse + specular * lobe`. If every sample
+            // sees the same amount of lobe, or the best fit would need negative
+            // specular reflection, the fit is just the mean.
+            let covariance = lobes
+                .iter()
+                .zip(&values)
+                .map(|(l, v)| (l - mean_lobe) * (v - mean))
+                .sum::<f32>()
+                / n;
+            let specular = if lobe_variance > 1e-6 {
+                (covariance / lobe_variance).max(0.0)
+            } else {
+                0.0
+            };
+            diffuse[channel] = mean - specular * mean_lobe;
+
+            let residual = lobes
+                .iter()
+                .zip(&values)
+                .map(|(l, v)| (v - diffuse[channel] - specular * l).powi(2))
+                .sum::<f32>()
+                / n;
+            if residual.sqrt() >= self.threshold {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(Color::from_vec3(diffuse)))
+    }
+}
@@ -0,0 +1,58 @@
+use super::{ConsistencyCheck, Sample};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::Color;
+use nalgebra_glm as glm;
+
+/// consistency checking via the voxel coloring algorithm. This assumes a
+/// lambertian radiance function which means that the color of a scene element
+/// should be view-independent. A set of views are deemed to be consistent if
+/// the standard deviation of their perceived colors is below `threshold` in
+/// every channel.
+pub struct Variance {
+    pub threshold: f32,
+}
+impl ConsistencyCheck for Variance {
+    fn check(&self, samples: &[Sample], _views: &[&View]) -> Result<Option<Color>> {
+        let length = samples.len();
+
+        // Calculate variance:
+        let sum_of_colors_squared: glm::Vec3 = samples
+            .iter()
+            .map(|s| s.color.component_mul(&s.color))
+            .fold(glm::vec3(0.0, 0.0, 0.0), |acc, c| acc + c);
+
+        let sum_of_colors = samples
+            .iter()
+            .fold(glm::vec3(0.0, 0.0, 0.0), |acc, s| acc + s.color);
+
+        let average_color = sum_of_colors / (length as f32);
+
+        // square sums
+        let sum_of_colors = sum_of_colors.component_mul(&sum_of_colors);
+
+        let variance = glm::vec3(
+            sum_of_colors_squared.y / length as f32,
+            sum_of_colors_squared.x / length as f32,
+            sum_of_colors_squared.z / length as f32,
+        ) - glm::vec3(
+            sum_of_colors.x / (length * length) as f32,
+            sum_of_colors.y / (length * length) as f32,
+            sum_of_colors.z / (length * length) as f32,
+        );
+
+        let threshold_squared = self.threshold * self.threshold;
+
+        // ensure each channel is below the threshold
+        if variance.x < threshold_squared
+            && variance.y < threshold_squared
+            && variance.z < threshold_squared
+        {
+            // Don't carve pixel
+            Ok(Some(Color::from_vec3(average_color)))
+        } else {
+            // Carve pixel
+            Ok(None)
+        }
+    }
+}
@@ -1,224 +1,514 @@
-use crate::brdf;
+use crate::brdf::{ConsistencyCheck, Sample};
+use crate::checkpoint::{self, Checkpoints, Progress};
+use crate::error::Result;
 use crate::view::View;
-use crate::volume::{Color, Volume, Voxel};
-use image::{GenericImageView, Pixel};
+use crate::volume::{Axis, Color, Volume, Voxel};
 use nalgebra_glm as glm;
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+/// The different algorithms that can be used to carve a volume
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Seitz and Kutulakos's space carving via repeated plane sweeps (`carve`)
+    SpaceCarving,
+    /// Generalized Voxel Coloring with exact visibility (`gvc::carve`)
+    Gvc,
+    /// Silhouette-only carving that ignores color (`visual_hull::carve`)
+    VisualHull,
+    /// Space carving that accumulates occupancy evidence across sweeps
+    /// (`probabilistic::carve`)
+    Probabilistic,
+}
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "space-carving" => Ok(Mode::SpaceCarving),
+            "gvc" => Ok(Mode::Gvc),
+            "visual-hull" => Ok(Mode::VisualHull),
+            "probabilistic" => Ok(Mode::Probabilistic),
+            _ => Err(format!(
+                "unknown mode `{}`, expected one of: space-carving, gvc, visual-hull, probabilistic",
+                s
+            )),
+        }
+    }
+}
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::SpaceCarving => write!(f, "space-carving"),
+            Mode::Gvc => write!(f, "gvc"),
+            Mode::VisualHull => write!(f, "visual-hull"),
+            Mode::Probabilistic => write!(f, "probabilistic"),
+        }
+    }
+}
 
-pub fn carve_voxel(
-    voxel: glm::IVec3,
-    volume: &Volume,
-    views: &mut Vec<&mut View>,
-    threshold: f32,
-) -> Option<Color> {
-    // Convert voxel-space coordinates to scene-space
-    let position = volume.voxel_to_position(voxel.x as usize, voxel.y as usize, voxel.z as usize);
-
-    // Convert to homogenous coordinates
-    let position = glm::vec4(
-        position[0] as f32,
-        position[1] as f32,
-        position[2] as f32,
-        1.0,
-    );
-
-    let mut colors_and_rays = vec![];
-    let mut masks = vec![];
-
-    for view in views {
-        let width = view.img.width() as i32;
-        let height = view.img.height() as i32;
-
-        // Back project scene element onto image
-        let back_projected: glm::Vec3 = view.camera.projection_matrix() * position;
-
-        // Scale by `z` to account for back projection ambiguity
-        let back_projected = glm::vec2(
-            back_projected.x / back_projected.z,
-            back_projected.y / back_projected.z,
-        );
+/// A regular grid of voxels that can be carved by plane sweeps. This is
+/// implemented by the dense `Volume`, and by each level of an `Octree` so that
+/// coarse-to-fine carving can reuse the same sweeps and consistency checks.
+pub trait Grid: Sync {
+    /// the number of voxels along `axis`
+    fn dimension(&self, axis: Axis) -> usize;
+    /// the indices of the voxels in the plane perpendicular to `axis` at
+    /// position `a` along it that exist at this grid's resolution. Must return
+    /// the voxels in the same order every time.
+    fn plane(&self, axis: Axis, a: usize) -> Vec<(usize, usize, usize)>;
+    /// the scene-space position of the center of a voxel
+    fn voxel_to_position(&self, x: usize, y: usize, z: usize) -> glm::Vec3;
+    /// the scene-space positions of the eight corners of a voxel
+    fn voxel_corners(&self, x: usize, y: usize, z: usize) -> [glm::Vec3; 8];
+    /// true if the voxel has been carved away
+    fn is_carved(&self, x: usize, y: usize, z: usize) -> bool;
+    /// true if any of the six voxels surrounding this voxel are missing
+    fn voxel_visible(&self, x: usize, y: usize, z: usize) -> Result<bool>;
+    /// update the state of a voxel
+    fn set(&mut self, x: usize, y: usize, z: usize, voxel: Voxel);
+}
 
-        let x = back_projected.x.floor() as i32;
-        let y = back_projected.y.floor() as i32;
+impl Grid for Volume {
+    fn dimension(&self, axis: Axis) -> usize {
+        Volume::dimension(self, axis)
+    }
+    fn plane(&self, axis: Axis, a: usize) -> Vec<(usize, usize, usize)> {
+        self.slice(axis, a).collect()
+    }
+    fn voxel_to_position(&self, x: usize, y: usize, z: usize) -> glm::Vec3 {
+        Volume::voxel_to_position(self, x, y, z)
+    }
+    fn voxel_corners(&self, x: usize, y: usize, z: usize) -> [glm::Vec3; 8] {
+        Volume::voxel_corners(self, x, y, z)
+    }
+    fn is_carved(&self, x: usize, y: usize, z: usize) -> bool {
+        Volume::is_carved(self, x, y, z)
+    }
+    fn voxel_visible(&self, x: usize, y: usize, z: usize) -> Result<bool> {
+        Volume::voxel_visible(self, x, y, z)
+    }
+    fn set(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+        Volume::set(self, x, y, z, voxel)
+    }
+}
 
-        // Skip view if back projected point falls outside captured image
-        if x < 0 || x >= width || y < 0 || y >= height {
-            // eprintln!("Back projected point is outside of image bounds");
-            continue;
-        }
+/// The outcome of checking a single voxel against a set of views
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// The views agree on the voxel's color, so it is kept with that color
+    Keep(Color),
+    /// The views disagree, so the voxel is not part of the scene
+    Carve,
+    /// No view can see the voxel, so there is no evidence either way
+    Unseen,
+}
 
-        // If this pixel of the image has already been matched to a scene
+/// The pixels of each view that were sampled while checking a voxel, in the
+/// same order as the views
+pub type UsedPixels = Vec<Vec<(u32, u32)>>;
+
+/// Decides the fate of a single voxel during a plane sweep without modifying
+/// the views, returning the verdict and the sampled pixels of each view (in the
+/// same order as the views) so that they can be masked if the voxel is kept.
+/// `check_voxel` is the usual judge; other modes supply their own.
+pub type Judge<'a, G> =
+    dyn Fn(glm::IVec3, &G, &[&View]) -> Result<(Verdict, UsedPixels)> + Sync + 'a;
+
+/// Gather the samples the given views see of a voxel. Every unmasked pixel
+/// covered by the projection of the voxel in a view is used as a sample. Also
+/// returns the sampled pixels of each view, in the same order as `views`.
+pub fn gather_samples<G: Grid + ?Sized>(
+    voxel: glm::IVec3,
+    volume: &G,
+    views: &[&View],
+) -> (Vec<Sample>, UsedPixels) {
+    let (x, y, z) = (voxel.x as usize, voxel.y as usize, voxel.z as usize);
+
+    // Convert voxel-space coordinates to scene-space
+    let position = volume.voxel_to_position(x, y, z);
+    let corners = volume.voxel_corners(x, y, z);
+
+    let mut samples = vec![];
+    // the pixels of each view that were sampled, so we can mask them later
+    let mut used_pixels = vec![];
+
+    for (i, view) in views.iter().enumerate() {
+        // Back project the voxel's corners onto the image. The footprint is
+        // empty if the voxel is behind the camera or outside the image.
+        //
+        // If a pixel of the image has already been matched to a scene
         // element, then that element occludes this new element so we
         // should skip it
-        if view.mask[y as usize][x as usize] {
-            continue;
-        }
-
-        let pix = view.img.get_pixel(x as u32, y as u32);
+        let pixels: Vec<(u32, u32)> = view
+            .footprint(&corners)
+            .into_iter()
+            .filter(|&(px, py)| !view.mask[py as usize][px as usize])
+            .collect();
 
         // calculate the vector from the scene voxel to the camera
-        let scene_to_camera = view.camera.translation() - position.xyz();
+        let scene_to_camera = view.camera.center() - position;
 
-        // Convert color from [0,255] to [0,1]
-        let color_vec = glm::vec3(
-            pix.channels()[0] as f32 / 255.0,
-            pix.channels()[1] as f32 / 255.0,
-            pix.channels()[2] as f32 / 255.0,
-        );
+        for &(px, py) in &pixels {
+            samples.push(view.sample(i, px, py, scene_to_camera));
+        }
+        used_pixels.push(pixels);
+    }
 
-        // Extract the mask value for this pixel in case we need to update it later
-        let mask_value = view
-            .mask
-            .get_mut(y as usize)
-            .unwrap()
-            .get_mut(x as usize)
-            .unwrap();
+    (samples, used_pixels)
+}
 
-        masks.push(mask_value);
-        colors_and_rays.push((color_vec, scene_to_camera));
+/// Check a single voxel for consistency against the given views without
+/// modifying them (see `gather_samples`). Along with the verdict, returns the
+/// sampled pixels of each view so that they can be masked if the voxel is
+/// kept.
+pub fn check_voxel<G: Grid + ?Sized>(
+    voxel: glm::IVec3,
+    volume: &G,
+    views: &[&View],
+    check: &dyn ConsistencyCheck,
+) -> Result<(Verdict, UsedPixels)> {
+    let (samples, used_pixels) = gather_samples(voxel, volume, views);
+
+    if samples.is_empty() {
+        return Ok((Verdict::Unseen, used_pixels));
     }
 
-    if colors_and_rays.len() == 0 {
-        return None;
-    } else {
-        let colors = colors_and_rays.iter().map(|(c, _)| *c).collect::<Vec<_>>();
-        let result = brdf::standard_consistency_check(&colors, threshold);
-
-        // Every time a pixel in an image is used to match with a scene element,
-        // we need to mask that pixel so it can't be used to match with
-        // another scene element
-        if let Some(_) = result {
-            for mask in masks {
-                *mask = true;
-            }
-        }
+    let verdict = match check.consistent(&samples, views)? {
+        Some(color) => Verdict::Keep(color),
+        None => Verdict::Carve,
+    };
+
+    Ok((verdict, used_pixels))
+}
 
-        return result;
+/// Every time a pixel in an image is used to match with a scene element, we
+/// need to mask that pixel so it can't be used to match with another scene
+/// element
+fn mask_pixels<'a>(views: impl Iterator<Item = &'a mut View>, used_pixels: UsedPixels) {
+    for (view, pixels) in views.zip(used_pixels) {
+        for (px, py) in pixels {
+            view.mask[py as usize][px as usize] = true;
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum XYZ {
-    X,
-    Y,
-    Z,
+/// Check a single voxel for consistency against the given views (see
+/// `check_voxel`). Pixels that were used to prove a voxel consistent are
+/// masked in their view so that they can't be reused for voxels it occludes.
+pub fn carve_voxel<G: Grid + ?Sized>(
+    voxel: glm::IVec3,
+    volume: &G,
+    views: &mut [&mut View],
+    check: &dyn ConsistencyCheck,
+) -> Result<Verdict> {
+    let shared: Vec<&View> = views.iter().map(|view| &**view).collect();
+    let (verdict, used_pixels) = check_voxel(voxel, volume, &shared, check)?;
+
+    if let Verdict::Keep(_) = verdict {
+        mask_pixels(views.iter_mut().map(|view| &mut **view), used_pixels);
+    }
+
+    Ok(verdict)
 }
 
-fn plane_sweep(
-    which_plane: XYZ,
+fn plane_sweep<G: Grid + ?Sized>(
+    which_plane: Axis,
     reversed: bool,
-    volume: &mut Volume,
-    views: &mut Vec<View>,
-    threshold: f32,
-) -> usize {
+    volume: &mut G,
+    views: &mut [View],
+    judge: &Judge<G>,
+) -> Result<usize> {
     // Our loops bounds depend on which axis the plane we're carving is aligned to
-    let loop_bounds = match which_plane {
-        XYZ::X => (volume.width, volume.depth, volume.height),
-        XYZ::Y => (volume.height, volume.width, volume.depth),
-        XYZ::Z => (volume.depth, volume.height, volume.width),
-    };
+    let num_planes = volume.dimension(which_plane);
     let mut voxels_carved = 0;
 
     let plane_bounds: Box<dyn Iterator<Item = _>> = if reversed {
-        Box::new((0..loop_bounds.0).rev())
+        Box::new((0..num_planes).rev())
     } else {
-        Box::new(0..loop_bounds.0)
+        Box::new(0..num_planes)
+    };
+
+    // The direction (along `which_plane`'s axis) the sweep moves in scene
+    // space. Voxel indices increase along +x but along -y and -z.
+    let sweep_direction = match (which_plane, reversed) {
+        (Axis::X, false) | (Axis::Y, true) | (Axis::Z, true) => 1.0,
+        (Axis::X, true) | (Axis::Y, false) | (Axis::Z, false) => -1.0,
+    };
+
+    // The index in its plane's list of every voxel of the current plane, laid
+    // out by in-plane coordinates (see `in_plane`)
+    let (width, height) = match which_plane {
+        Axis::X => (volume.dimension(Axis::Y), volume.dimension(Axis::Z)),
+        Axis::Y => (volume.dimension(Axis::X), volume.dimension(Axis::Z)),
+        Axis::Z => (volume.dimension(Axis::X), volume.dimension(Axis::Y)),
     };
+    let mut positions = vec![NOT_IN_PLANE; width * height];
 
     for a in plane_bounds {
         // Calculate the plane's position in scene space
         let plane_in_world_space = match which_plane {
-            XYZ::X => volume.voxel_to_position(a, 0, 0).x,
-            XYZ::Y => volume.voxel_to_position(0, a, 0).y,
-            XYZ::Z => volume.voxel_to_position(0, 0, a).z,
+            Axis::X => volume.voxel_to_position(a, 0, 0).x,
+            Axis::Y => volume.voxel_to_position(0, a, 0).y,
+            Axis::Z => volume.voxel_to_position(0, 0, a).z,
         };
-        // Find all views which are on one side of the current plane we're carving
-        // so that occlusion is consistent.
-        let view_is_valid = |t: glm::Vec3| match which_plane {
-            XYZ::X => t[0] < plane_in_world_space,
-            XYZ::Y => t[1] < plane_in_world_space,
-            XYZ::Z => t[2] > plane_in_world_space,
+        // Find all views whose camera centers are on the side of the current
+        // plane that has already been swept so that occlusion is consistent:
+        // those cameras see every plane we have already carved before this one.
+        let view_is_valid = |center: glm::Vec3| {
+            (center[which_plane.index()] - plane_in_world_space) * sweep_direction < 0.0
         };
-        let mut non_occluded_views: Vec<_> = views
-            .iter_mut()
-            .filter(|view| view_is_valid(view.camera.translation()))
+        let non_occluded_views: Vec<usize> = (0..views.len())
+            .filter(|&i| view_is_valid(views[i].camera.center()))
             .collect();
 
+        // No camera can see this plane without looking through unswept
+        // voxels, so there's no evidence to carve anything with
+        if non_occluded_views.is_empty() {
+            continue;
+        }
+
         // println!("Carving plane {} at location {}", a, plane_in_world_space);
         // println!("{} views are valid", non_occluded_views.len());
 
-        for b in 0..loop_bounds.1 {
-            for c in 0..loop_bounds.2 {
-                // Convert loop values into xyz coordinates
-                let (x, y, z) = match which_plane {
-                    XYZ::X => (a, c, b),
-                    XYZ::Y => (b, a, c),
-                    XYZ::Z => (c, b, a),
-                };
-
-                if *volume.get_voxel(x, y, z) == Voxel::Carved || !volume.voxel_visible(x, y, z) {
-                    continue;
+        // Look up where each voxel is in the plane's list by its in-plane
+        // coordinates, since sparse grids only list some of the voxels
+        let plane = volume.plane(which_plane, a);
+        for (i, &voxel) in plane.iter().enumerate() {
+            let (u, v) = in_plane(which_plane, voxel);
+            positions[u + v * width] = i;
+        }
+        // Voxels of this plane that have already been checked during this sweep
+        let mut checked = vec![false; plane.len()];
+
+        // Carving a voxel can expose its neighbors in the same plane, so after
+        // checking the whole plane, keep checking the neighbors of the voxels
+        // carved in the previous round until the plane settles
+        let mut to_check: Vec<usize> = (0..plane.len()).collect();
+        while !to_check.is_empty() {
+            // Find the voxels to check that are on the surface and haven't
+            // been checked yet
+            let mut candidates = vec![];
+            for i in to_check {
+                let (x, y, z) = plane[i];
+                if !checked[i] && !volume.is_carved(x, y, z) && volume.voxel_visible(x, y, z)? {
+                    checked[i] = true;
+                    candidates.push((x, y, z));
                 }
+            }
 
-                // Perform the voxel carving calculation for this voxel
-                let pos_voxel_space = glm::vec3(x as i32, y as i32, z as i32);
-                let result =
-                    carve_voxel(pos_voxel_space, &volume, &mut non_occluded_views, threshold);
-
-                match result {
-                    None => {
+            // Perform the voxel carving calculation for every voxel in the plane
+            // in parallel. Every voxel sees the masks and volume as they were at
+            // the start of this round, so the result doesn't depend on the order
+            // voxels are checked in.
+            let results = {
+                let shared: Vec<&View> = non_occluded_views.iter().map(|&i| &views[i]).collect();
+                candidates
+                    .par_iter()
+                    .map(|&(x, y, z)| {
+                        let pos_voxel_space = glm::vec3(x as i32, y as i32, z as i32);
+                        judge(pos_voxel_space, volume, &shared)
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            };
+
+            // Commit the results in a fixed order once the whole plane is
+            // checked, and check the voxels that may have been exposed next, in
+            // the order of the plane
+            let mut exposed = BTreeSet::new();
+            for ((x, y, z), (verdict, used_pixels)) in candidates.into_iter().zip(results) {
+                match verdict {
+                    Verdict::Carve => {
                         voxels_carved += 1;
-                        *volume.get_voxel(x, y, z) = Voxel::Carved;
+                        volume.set(x, y, z, Voxel::Carved);
+                        exposed.extend(
+                            plane_neighbors(which_plane, (x, y, z))
+                                .iter()
+                                .map(|&neighbor| in_plane(which_plane, neighbor))
+                                .filter(|&(u, v)| u < width && v < height)
+                                .map(|(u, v)| positions[u + v * width])
+                                .filter(|&i| i != NOT_IN_PLANE && !checked[i]),
+                        );
                     }
-                    Some(color) => {
-                        *volume.get_voxel(x, y, z) = Voxel::Colored(color);
+                    Verdict::Keep(color) => {
+                        volume.set(x, y, z, Voxel::Colored(color));
+                        mask_pixels(
+                            views
+                                .iter_mut()
+                                .enumerate()
+                                .filter(|(i, _)| non_occluded_views.contains(i))
+                                .map(|(_, view)| view),
+                            used_pixels,
+                        );
                     }
+                    Verdict::Unseen => {}
                 }
             }
+            to_check = exposed.into_iter().collect();
+        }
+
+        for &voxel in &plane {
+            let (u, v) = in_plane(which_plane, voxel);
+            positions[u + v * width] = NOT_IN_PLANE;
         }
     }
 
-    return voxels_carved;
+    Ok(voxels_carved)
 }
 
-/// Given an uncarved volume and a set of views, carve the volume so it is
-/// consistent with the views
-pub fn carve(volume: &mut Volume, views: &mut Vec<View>, threshold: f32) {
-    let mut total_carved = 0;
+/// marks a spot of a sparse plane that has no voxel
+const NOT_IN_PLANE: usize = usize::MAX;
 
-    // Carve until convergence
-    loop {
-        let mut carved_this_loop = 0;
-        let sweeps = vec![
-            (XYZ::X, false),
-            (XYZ::Y, false),
-            (XYZ::Z, false),
-            (XYZ::X, true),
-            (XYZ::Y, true),
-            (XYZ::Z, true),
-        ];
-
-        for (which_plane, reversed) in sweeps {
-            for view in views.iter_mut() {
-                view.reset_mask();
-            }
+/// the coordinates of a voxel within its plane along `axis`, leaving out the
+/// coordinate along `axis`
+fn in_plane(axis: Axis, (x, y, z): (usize, usize, usize)) -> (usize, usize) {
+    match axis {
+        Axis::X => (y, z),
+        Axis::Y => (x, z),
+        Axis::Z => (x, y),
+    }
+}
+
+/// the four voxels next to `(x, y, z)` within its plane along `axis`. Voxels
+/// past the low edge of the volume wrap around to indices that aren't in any
+/// plane.
+fn plane_neighbors(axis: Axis, (x, y, z): (usize, usize, usize)) -> [(usize, usize, usize); 4] {
+    let below = |i: usize| i.wrapping_sub(1);
+    match axis {
+        Axis::X => [
+            (x, below(y), z),
+            (x, y + 1, z),
+            (x, y, below(z)),
+            (x, y, z + 1),
+        ],
+        Axis::Y => [
+            (below(x), y, z),
+            (x + 1, y, z),
+            (x, y, below(z)),
+            (x, y, z + 1),
+        ],
+        Axis::Z => [
+            (below(x), y, z),
+            (x + 1, y, z),
+            (x, below(y), z),
+            (x, y + 1, z),
+        ],
+    }
+}
+
+/// Given an uncarved volume (or any other `Grid`) and a set of views, carve
+/// the volume so it is consistent with the views
+pub fn carve<G: Grid + ?Sized>(
+    volume: &mut G,
+    views: &mut [View],
+    check: &dyn ConsistencyCheck,
+) -> Result<()> {
+    carve_with(volume, views, &|voxel, volume, views| {
+        check_voxel(voxel, volume, views, check)
+    })
+}
+
+/// Carve the volume with repeated plane sweeps until convergence, letting
+/// `judge` decide the fate of each surface voxel
+pub fn carve_with<G: Grid + ?Sized>(
+    volume: &mut G,
+    views: &mut [View],
+    judge: &Judge<G>,
+) -> Result<()> {
+    sweep(
+        volume,
+        views,
+        judge,
+        Progress::default(),
+        &mut |_, _| Ok(()),
+    )?;
+    Ok(())
+}
 
-            let voxels_carved = plane_sweep(which_plane, reversed, volume, views, threshold);
+/// Carve `volume` like `carve`, starting after the sweeps in `progress` (e.g.
+/// from `checkpoint::load`) and saving it as `checkpoints` asks, if given.
+/// Checkpoints are only taken between sweeps, and the views' masks are
+/// cleared at the start of every sweep, so the volume and `progress` are all
+/// there is to the state of a carve: resuming from a checkpoint carves
+/// exactly what an uninterrupted carve would have.
+pub fn carve_checkpointed(
+    volume: &mut Volume,
+    views: &mut [View],
+    check: &dyn ConsistencyCheck,
+    progress: Progress,
+    checkpoints: Option<&Checkpoints>,
+) -> Result<()> {
+    let mut last_checkpoint = Instant::now();
+    let judge = |voxel, volume: &Volume, views: &[&View]| check_voxel(voxel, volume, views, check);
+    sweep(volume, views, &judge, progress, &mut |volume, progress| {
+        let checkpoints = match checkpoints {
+            Some(checkpoints) => checkpoints,
+            None => return Ok(()),
+        };
+        if progress.converged || last_checkpoint.elapsed() >= checkpoints.interval {
+            checkpoint::save(volume, progress, &checkpoints.filename)?;
             println!(
-                "Carved {} voxels on {} {:?} sweep",
-                voxels_carved,
-                if reversed { "reversed" } else { "forward" },
-                which_plane
+                "Saved a checkpoint after {} sweeps to {}",
+                progress.sweeps, checkpoints.filename
             );
+            last_checkpoint = Instant::now();
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// The directions of the plane sweeps in one round, in order
+const SWEEPS: [(Axis, bool); 6] = [
+    (Axis::X, false),
+    (Axis::Y, false),
+    (Axis::Z, false),
+    (Axis::X, true),
+    (Axis::Y, true),
+    (Axis::Z, true),
+];
+
+/// Sweep in every direction in turn, starting after the sweeps in `progress`,
+/// until a whole round carves nothing. `after_sweep` is called with the
+/// progress after every sweep.
+fn sweep<G: Grid + ?Sized>(
+    volume: &mut G,
+    views: &mut [View],
+    judge: &Judge<G>,
+    mut progress: Progress,
+    after_sweep: &mut dyn FnMut(&G, Progress) -> Result<()>,
+) -> Result<Progress> {
+    let mut total_carved = 0;
 
-            carved_this_loop += voxels_carved;
+    // Carve until convergence
+    while !progress.converged {
+        let (which_plane, reversed) = SWEEPS[progress.sweeps % SWEEPS.len()];
+        for view in views.iter_mut() {
+            view.reset_mask();
         }
 
-        if carved_this_loop == 0 {
-            break;
-        } else {
-            total_carved += carved_this_loop;
+        let voxels_carved = plane_sweep(which_plane, reversed, volume, views, judge)?;
+        println!(
+            "Carved {} voxels on {} {:?} sweep",
+            voxels_carved,
+            if reversed { "reversed" } else { "forward" },
+            which_plane
+        );
+        total_carved += voxels_carved;
+        progress.carved_this_round += voxels_carved;
+        progress.sweeps += 1;
+
+        if progress.sweeps.is_multiple_of(SWEEPS.len()) {
+            progress.converged = progress.carved_this_round == 0;
+            progress.carved_this_round = 0;
         }
+        after_sweep(volume, progress)?;
     }
 
     println!("Carved {} voxels", total_carved);
+
+    Ok(progress)
 }
@@ -0,0 +1,238 @@
+//! Saving a volume part way through carving, so that a long carve can pick up
+//! where it stopped after a crash. A saved volume holds the voxel size,
+//! bounding box, dimensions, the state and color of every voxel, and how far
+//! carving got, in a compact binary format of its own. It can also be
+//! exported to any format later on without carving it again.
+use crate::error::{CarvingError, Result};
+use crate::volume::Volume;
+use nalgebra_glm as glm;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// the start of every saved volume
+const MAGIC: &[u8; 8] = b"SCVOLUME";
+/// the version of the format, bumped whenever it changes
+const VERSION: u32 = 1;
+
+/// How far carving a volume got
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Progress {
+    /// the number of plane sweeps done so far. The sweeps go through the six
+    /// directions in a fixed order, so this also says which one is next.
+    pub sweeps: usize,
+    /// the number of voxels carved by the sweeps of the current round so
+    /// far, which decides whether the round ends carving
+    pub carved_this_round: usize,
+    /// true once a whole round of sweeps carved nothing more
+    pub converged: bool,
+}
+
+/// the time between two checkpoints, if not given
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Where and how often `carve::carve_checkpointed` saves the volume
+#[derive(Debug, Clone)]
+pub struct Checkpoints {
+    pub filename: String,
+    /// the least time between two checkpoints. The volume is saved after the
+    /// first sweep that ends at least this long after the last checkpoint,
+    /// and once carving is done.
+    pub interval: Duration,
+}
+
+/// Save `volume` and how far carving it got to `filename`. The volume is
+/// written to a temporary file first and then renamed, so a crash while
+/// saving leaves the previous checkpoint intact.
+pub fn save(volume: &Volume, progress: Progress, filename: &str) -> Result<()> {
+    let temporary = format!("{}.tmp", filename);
+    let write = || -> io::Result<()> {
+        let mut out = BufWriter::new(fs::File::create(&temporary)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(progress.sweeps as u64).to_le_bytes())?;
+        out.write_all(&(progress.carved_this_round as u64).to_le_bytes())?;
+        out.write_all(&[progress.converged as u8])?;
+        volume.write_to(&mut out)?;
+        out.flush()
+    };
+    write().map_err(|e| CarvingError::io(&temporary, e))?;
+    fs::rename(&temporary, filename).map_err(|e| CarvingError::io(filename, e))
+}
+
+/// Load a volume saved with `save`, along with how far carving it got
+pub fn load(filename: &str) -> Result<(Volume, Progress)> {
+    let read = || -> io::Result<(Volume, Progress)> {
+        let mut input = BufReader::new(fs::File::open(filename)?);
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut header = [0; 8 + 4 + 8 + 8 + 1];
+        input
+            .read_exact(&mut header)
+            .map_err(|_| invalid("not a saved volume"))?;
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a saved volume"));
+        }
+        let version = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "saved with format version {}, but only version {} can be read",
+                version, VERSION
+            )));
+        }
+        let count = |bytes: &[u8]| {
+            let mut count = [0; 8];
+            count.copy_from_slice(bytes);
+            u64::from_le_bytes(count) as usize
+        };
+        let progress = Progress {
+            sweeps: count(&header[12..20]),
+            carved_this_round: count(&header[20..28]),
+            converged: header[28] != 0,
+        };
+
+        let volume = Volume::read_from(&mut input)?;
+        if input.read(&mut [0])? != 0 {
+            return Err(invalid("unexpected data after the volume"));
+        }
+        Ok((volume, progress))
+    };
+    read().map_err(|e| CarvingError::io(Path::new(filename), e))
+}
+
+/// Make sure a saved volume was carved with the given voxel size and bounding
+/// box, so that resuming it carves the same volume as the interrupted run
+pub fn check_matches(
+    volume: &Volume,
+    voxel_size: f32,
+    front_top_left: glm::Vec3,
+    back_bottom_right: glm::Vec3,
+) -> Result<()> {
+    let mismatch = |what: &str, saved: String, current: String| {
+        Err(CarvingError::CheckpointMismatch(format!(
+            "it has {} {} instead of {}",
+            what, saved, current
+        )))
+    };
+
+    if volume.voxel_size != voxel_size {
+        return mismatch(
+            "voxel size",
+            volume.voxel_size.to_string(),
+            voxel_size.to_string(),
+        );
+    }
+    if volume.front_top_left != front_top_left || volume.back_bottom_right != back_bottom_right {
+        return mismatch(
+            "bounding box",
+            format!(
+                "{:?} to {:?}",
+                volume.front_top_left.as_slice(),
+                volume.back_bottom_right.as_slice()
+            ),
+            format!(
+                "{:?} to {:?}",
+                front_top_left.as_slice(),
+                back_bottom_right.as_slice()
+            ),
+        );
+    }
+    let dimensions = Volume::dimensions_for(voxel_size, front_top_left, back_bottom_right)?;
+    if (volume.width, volume.height, volume.depth) != dimensions {
+        return mismatch(
+            "dimensions",
+            format!("{}x{}x{}", volume.width, volume.height, volume.depth),
+            format!("{}x{}x{}", dimensions.0, dimensions.1, dimensions.2),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::{Color, Voxel};
+
+    /// a small volume with some voxels carved and some colored
+    fn volume() -> Volume {
+        let mut volume =
+            Volume::new(0.1, glm::vec3(-0.3, 0.25, 0.2), glm::vec3(0.3, -0.25, -0.4)).unwrap();
+        for i in 0..volume.len() {
+            let (x, y, z) = volume.coords(i);
+            let voxel = match i % 3 {
+                0 => Voxel::Carved,
+                1 => Voxel::Colored(Color::from_rgb8([x as u8 * 40, y as u8 * 40, z as u8 * 40])),
+                _ => Voxel::Untouched,
+            };
+            volume.set(x, y, z, voxel);
+        }
+        volume
+    }
+
+    fn filename(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "space-carving-{}-{}.volume",
+                name,
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn loads_what_was_saved() {
+        let volume = volume();
+        let progress = Progress {
+            sweeps: 14,
+            carved_this_round: 123,
+            converged: false,
+        };
+        let filename = filename("round-trip");
+        save(&volume, progress, &filename).unwrap();
+        let (loaded, loaded_progress) = load(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+
+        assert_eq!(loaded_progress, progress);
+        assert_eq!(loaded.voxel_size, volume.voxel_size);
+        assert_eq!(loaded.front_top_left, volume.front_top_left);
+        assert_eq!(loaded.back_bottom_right, volume.back_bottom_right);
+        assert_eq!(
+            (loaded.width, loaded.height, loaded.depth),
+            (volume.width, volume.height, volume.depth)
+        );
+        assert!(loaded.voxels().eq(volume.voxels()));
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let filename = filename("damaged");
+        save(&volume(), Progress::default(), &filename).unwrap();
+        let saved = fs::read(&filename).unwrap();
+
+        // Cut short, with data after the volume, and not a volume at all
+        fs::write(&filename, &saved[..saved.len() - 1]).unwrap();
+        assert!(load(&filename).is_err());
+        fs::write(&filename, [&saved[..], &[0]].concat()).unwrap();
+        assert!(load(&filename).is_err());
+        fs::write(&filename, b"ply\nformat ascii 1.0\n").unwrap();
+        assert!(load(&filename).is_err());
+        fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_volumes() {
+        let volume = volume();
+        let (front_top_left, back_bottom_right) = (volume.front_top_left, volume.back_bottom_right);
+        assert!(check_matches(&volume, 0.1, front_top_left, back_bottom_right).is_ok());
+        assert!(check_matches(&volume, 0.05, front_top_left, back_bottom_right).is_err());
+        assert!(check_matches(
+            &volume,
+            0.1,
+            front_top_left,
+            back_bottom_right + glm::vec3(0.1, 0.0, 0.0)
+        )
+        .is_err());
+    }
+}
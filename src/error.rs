@@ -0,0 +1,116 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while importing a dataset, carving it, or
+/// exporting the result
+#[derive(Debug)]
+pub enum CarvingError {
+    /// A metadata or dataset description file couldn't be parsed. `line` is
+    /// 1-indexed.
+    MetadataParse {
+        file: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// An image referenced by a dataset couldn't be opened
+    MissingImage {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    /// An image that has to line up with a view's image (e.g. a background
+    /// plate) has a different size
+    ImageSizeMismatch {
+        path: PathBuf,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    /// The bounding box (or voxel size) doesn't describe a usable volume
+    InvalidBoundingBox(String),
+    /// The images selected from a dataset don't exist, or there are none
+    InvalidSelection(String),
+    /// Voxel indices outside of the volume were requested
+    VoxelOutOfBounds { x: usize, y: usize, z: usize },
+    /// A consistency check was asked to check an empty set of colors
+    NoSamples,
+    /// The result can't be written in the format that was asked for, e.g. a
+    /// mesh as a voxel format
+    UnsupportedFormat(String),
+    /// A saved volume was resumed with a different voxel size or bounding box
+    /// than it was carved with
+    CheckpointMismatch(String),
+    /// Reading or writing a file failed
+    Io { path: PathBuf, source: io::Error },
+}
+
+pub type Result<T> = std::result::Result<T, CarvingError>;
+
+impl CarvingError {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        CarvingError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn parse(path: impl Into<PathBuf>, line: usize, message: impl Into<String>) -> Self {
+        CarvingError::MetadataParse {
+            file: path.into(),
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for CarvingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CarvingError::MetadataParse {
+                file,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", file.display(), line, message),
+            CarvingError::MissingImage { path, source } => {
+                write!(f, "couldn't open image {}: {}", path.display(), source)
+            }
+            CarvingError::InvalidBoundingBox(reason) => {
+                write!(f, "invalid bounding box: {}", reason)
+            }
+            CarvingError::InvalidSelection(reason) => {
+                write!(f, "invalid image selection: {}", reason)
+            }
+            CarvingError::VoxelOutOfBounds { x, y, z } => {
+                write!(f, "voxel ({}, {}, {}) is out of bounds", x, y, z)
+            }
+            CarvingError::NoSamples => write!(f, "can't check consistency of no points"),
+            CarvingError::UnsupportedFormat(reason) => write!(f, "unsupported format: {}", reason),
+            CarvingError::CheckpointMismatch(reason) => {
+                write!(f, "saved volume doesn't match this carve: {}", reason)
+            }
+            CarvingError::ImageSizeMismatch {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} is {}x{} but should be {}x{} like its image",
+                path.display(),
+                found.0,
+                found.1,
+                expected.0,
+                expected.1
+            ),
+            CarvingError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for CarvingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CarvingError::MissingImage { source, .. } => Some(source),
+            CarvingError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::error::{CarvingError, Result};
+use crate::mesh::Mesh;
+use crate::volume::Color;
+use nalgebra_glm as glm;
+use serde_json::json;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+
+// glTF constants
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const POINTS: u32 = 0;
+const TRIANGLES: u32 = 4;
+
+/// glTF colors are linear, while ours are sRGB like the images they came from
+fn linear(color: Color) -> [f32; 3] {
+    let channel = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [channel(color.r), channel(color.g), channel(color.b)]
+}
+
+/// Collects the binary buffer of a glTF file along with the buffer views and
+/// accessors that describe it
+#[derive(Default)]
+struct Buffer {
+    data: Vec<u8>,
+    views: Vec<serde_json::Value>,
+    accessors: Vec<serde_json::Value>,
+}
+impl Buffer {
+    /// add an accessor for `values`, returning its index. `accessor` holds the
+    /// accessor's type and any bounds.
+    fn push(
+        &mut self,
+        values: impl Iterator<Item = [u8; 4]>,
+        count: usize,
+        target: u32,
+        component_type: u32,
+        mut accessor: serde_json::Value,
+    ) -> usize {
+        let offset = self.data.len();
+        for value in values {
+            self.data.extend(value);
+        }
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.data.len() - offset,
+            "target": target,
+        }));
+        accessor["bufferView"] = json!(self.views.len() - 1);
+        accessor["componentType"] = json!(component_type);
+        accessor["count"] = json!(count);
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+    /// add an accessor for a list of 3d vectors, returning its index
+    fn vectors(&mut self, vectors: &[glm::Vec3], bounds: bool) -> usize {
+        let mut accessor = json!({ "type": "VEC3" });
+        if bounds {
+            let (min, max) = vectors.iter().fold(
+                (
+                    glm::vec3(f32::MAX, f32::MAX, f32::MAX),
+                    glm::vec3(f32::MIN, f32::MIN, f32::MIN),
+                ),
+                |(min, max), v| (glm::min2(&min, v), glm::max2(&max, v)),
+            );
+            accessor["min"] = json!([min.x, min.y, min.z]);
+            accessor["max"] = json!([max.x, max.y, max.z]);
+        }
+        let values = vectors.iter().flat_map(|v| [v.x, v.y, v.z]);
+        self.push(
+            values.map(f32::to_le_bytes),
+            vectors.len(),
+            ARRAY_BUFFER,
+            FLOAT,
+            accessor,
+        )
+    }
+}
+
+/// write a mesh to `filename` as a self-contained binary glTF 2.0 file, with
+/// vertex normals and colors. glTF has no face colors, so a mesh with face
+/// colors (or without normals) is written flat shaded, with its own vertices
+/// for every face. Faces are split into triangles, and a mesh without faces
+/// is written as points.
+pub fn write_glb(mesh: &Mesh, filename: &str) -> Result<()> {
+    let has_faces = !mesh.indices.is_empty();
+    let mesh = if has_faces && (!mesh.face_colors.is_empty() || mesh.normals.is_empty()) {
+        mesh.flat_shaded().triangulated()
+    } else {
+        mesh.triangulated()
+    };
+
+    let mut buffer = Buffer::default();
+    let mut attributes = json!({ "POSITION": buffer.vectors(&mesh.positions, true) });
+    if !mesh.normals.is_empty() {
+        attributes["NORMAL"] = json!(buffer.vectors(&mesh.normals, false));
+    }
+    if !mesh.vertex_colors.is_empty() {
+        let colors: Vec<glm::Vec3> = mesh
+            .vertex_colors
+            .iter()
+            .map(|&color| glm::Vec3::from(linear(color)))
+            .collect();
+        attributes["COLOR_0"] = json!(buffer.vectors(&colors, false));
+    }
+    let mut primitive = json!({
+        "attributes": attributes,
+        "material": 0,
+        "mode": if has_faces { TRIANGLES } else { POINTS },
+    });
+    if has_faces {
+        primitive["indices"] = json!(buffer.push(
+            mesh.indices.iter().map(|i| i.to_le_bytes()),
+            mesh.indices.len(),
+            ELEMENT_ARRAY_BUFFER,
+            UNSIGNED_INT,
+            json!({ "type": "SCALAR" }),
+        ));
+    }
+
+    // The default material is fully metallic, which shows up almost black
+    // without an environment map
+    let document = json!({
+        "asset": { "version": "2.0", "generator": "space-carving" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "meshes": [{ "primitives": [primitive] }],
+        "materials": [{
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [1.0, 1.0, 1.0, 1.0],
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            },
+        }],
+        "buffers": [{ "byteLength": buffer.data.len() }],
+        "bufferViews": buffer.views,
+        "accessors": buffer.accessors,
+    });
+
+    // Both chunks have to be padded to four bytes, the JSON with spaces
+    let mut json = document.to_string().into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    let mut data = buffer.data;
+    data.resize(data.len().next_multiple_of(4), 0);
+    let length = 12 + 8 + json.len() + 8 + data.len();
+
+    let file = fs::File::create(filename).map_err(|e| CarvingError::io(filename, e))?;
+    let mut out = BufWriter::new(file);
+    let write = |out: &mut BufWriter<fs::File>| -> io::Result<()> {
+        out.write_all(b"glTF")?;
+        out.write_all(&2u32.to_le_bytes())?;
+        out.write_all(&(length as u32).to_le_bytes())?;
+        out.write_all(&(json.len() as u32).to_le_bytes())?;
+        out.write_all(b"JSON")?;
+        out.write_all(&json)?;
+        out.write_all(&(data.len() as u32).to_le_bytes())?;
+        out.write_all(b"BIN\0")?;
+        out.write_all(&data)?;
+        out.flush()
+    };
+    write(&mut out).map_err(|e| CarvingError::io(filename, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh;
+    use crate::volume::{Volume, Voxel};
+
+    /// a 2x2x2 volume with only one voxel left, in mid gray
+    fn single_voxel() -> Volume {
+        let mut volume =
+            Volume::new(1.0, glm::vec3(0.0, 2.0, 2.0), glm::vec3(2.0, 0.0, 0.0)).unwrap();
+        for i in 0..volume.len() {
+            let (x, y, z) = volume.coords(i);
+            volume.set(x, y, z, Voxel::Carved);
+        }
+        volume.set(0, 0, 0, Voxel::Colored(Color::from_rgb8([128, 128, 128])));
+        volume
+    }
+
+    /// write `mesh` as glb and read back its JSON document and binary buffer
+    fn round_trip(name: &str, mesh: &Mesh) -> (serde_json::Value, Vec<u8>) {
+        let filename = std::env::temp_dir()
+            .join(format!("space-carving-{}-{}.glb", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        write_glb(mesh, &filename).unwrap();
+        let data = fs::read(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+
+        let word =
+            |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        assert_eq!(&data[..4], b"glTF");
+        assert_eq!(word(4), 2);
+        assert_eq!(word(8) as usize, data.len());
+        let json_length = word(12) as usize;
+        assert_eq!(&data[16..20], b"JSON");
+        let document = serde_json::from_slice(&data[20..20 + json_length]).unwrap();
+        let bin = 20 + json_length;
+        assert_eq!(&data[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(bin + 8 + word(bin) as usize, data.len());
+        assert_eq!(json_length % 4, 0);
+        (document, data[bin + 8..].to_vec())
+    }
+
+    /// the floats an accessor points at
+    fn floats(
+        document: &serde_json::Value,
+        buffer: &[u8],
+        accessor: &serde_json::Value,
+    ) -> Vec<f32> {
+        let accessor = &document["accessors"][accessor.as_u64().unwrap() as usize];
+        let view = &document["bufferViews"][accessor["bufferView"].as_u64().unwrap() as usize];
+        let offset = view["byteOffset"].as_u64().unwrap() as usize;
+        let length = view["byteLength"].as_u64().unwrap() as usize;
+        assert_eq!(accessor["componentType"], FLOAT);
+        buffer[offset..offset + length]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn writes_face_colors_flat_shaded() {
+        let (document, buffer) = round_trip("faces", &mesh::exposed_faces(&single_voxel(), false));
+        let primitive = &document["meshes"][0]["primitives"][0];
+        assert_eq!(primitive["mode"], TRIANGLES);
+
+        // Four vertices of its own for each of the six faces, and two
+        // triangles per face
+        let attributes = &primitive["attributes"];
+        let position = &document["accessors"][attributes["POSITION"].as_u64().unwrap() as usize];
+        assert_eq!(position["count"], 24);
+        assert_eq!(position["min"], json!([0.0, 1.0, 1.0]));
+        assert_eq!(position["max"], json!([1.0, 2.0, 2.0]));
+        let indices = &document["accessors"][primitive["indices"].as_u64().unwrap() as usize];
+        assert_eq!(indices["count"], 36);
+        assert_eq!(indices["componentType"], UNSIGNED_INT);
+
+        let normals = floats(&document, &buffer, &attributes["NORMAL"]);
+        assert_eq!(normals.len(), 24 * 3);
+        for normal in normals.chunks(3) {
+            assert!((glm::length(&glm::make_vec3(normal)) - 1.0).abs() < 1e-6);
+        }
+
+        // Colors are converted from sRGB to linear
+        let colors = floats(&document, &buffer, &attributes["COLOR_0"]);
+        for channel in colors {
+            assert!((channel - 0.2158605).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn writes_point_clouds_as_points() {
+        let (document, _) = round_trip("points", &mesh::point_cloud(&single_voxel()));
+        let primitive = &document["meshes"][0]["primitives"][0];
+        assert_eq!(primitive["mode"], POINTS);
+        assert!(primitive.get("indices").is_none());
+        let position =
+            &document["accessors"][primitive["attributes"]["POSITION"].as_u64().unwrap() as usize];
+        assert_eq!(position["count"], 1);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::error::{CarvingError, Result};
+use crate::mesh::Mesh;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+mod gltf;
+mod nrrd;
+mod obj;
+mod ply;
+mod stl;
+mod vox;
+
+pub use gltf::write_glb;
+pub use nrrd::{write_nrrd, write_volume_nrrd};
+pub use obj::write_obj;
+pub use ply::{write_mesh_ply, write_ply, Encoding};
+pub use stl::write_stl;
+pub use vox::write_vox;
+
+/// The file formats the result can be written in. `Vox` and `Nrrd` hold the
+/// voxels themselves, the others a mesh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Stanford `ply` (`write_mesh_ply`)
+    Ply,
+    /// Wavefront `obj` (`write_obj`)
+    Obj,
+    /// binary `stl` (`write_stl`)
+    Stl,
+    /// binary glTF 2.0 (`write_glb`)
+    Glb,
+    /// MagicaVoxel (`write_vox`)
+    Vox,
+    /// an RGBA NRRD volume (`write_volume_nrrd`)
+    Nrrd,
+}
+impl Format {
+    /// the format the extension of `filename` asks for, if it's one of them.
+    /// Detached NRRD headers (`.nhdr`) are NRRD too.
+    pub fn from_path(filename: &str) -> Option<Format> {
+        let extension = Path::new(filename).extension()?;
+        match extension.to_string_lossy().to_lowercase().as_str() {
+            "nhdr" => Some(Format::Nrrd),
+            extension => extension.parse().ok(),
+        }
+    }
+}
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ply" => Ok(Format::Ply),
+            "obj" => Ok(Format::Obj),
+            "stl" => Ok(Format::Stl),
+            "glb" => Ok(Format::Glb),
+            "vox" => Ok(Format::Vox),
+            "nrrd" => Ok(Format::Nrrd),
+            _ => Err(format!(
+                "unknown output format `{}`, expected one of: ply, obj, stl, glb, vox, nrrd",
+                s
+            )),
+        }
+    }
+}
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Ply => write!(f, "ply"),
+            Format::Obj => write!(f, "obj"),
+            Format::Stl => write!(f, "stl"),
+            Format::Glb => write!(f, "glb"),
+            Format::Vox => write!(f, "vox"),
+            Format::Nrrd => write!(f, "nrrd"),
+        }
+    }
+}
+
+/// write a mesh to `filename` in the given format, which has to be a mesh
+/// format. `encoding` only matters for `ply` files.
+pub fn write_mesh(mesh: &Mesh, filename: &str, format: Format, encoding: Encoding) -> Result<()> {
+    match format {
+        Format::Ply => write_mesh_ply(mesh, filename, encoding),
+        Format::Obj => write_obj(mesh, filename),
+        Format::Stl => write_stl(mesh, filename),
+        Format::Glb => write_glb(mesh, filename),
+        Format::Vox | Format::Nrrd => Err(CarvingError::UnsupportedFormat(format!(
+            "a mesh can't be written as {}",
+            format
+        ))),
+    }
+}
@@ -0,0 +1,216 @@
+//! NRRD volumes, which 3D Slicer, ParaView and most other volume tools load.
+//! The voxel spacing and the position of the first voxel are recorded, so a
+//! volume lines up with the exported meshes. A `.nhdr` filename writes a
+//! detached header, with the samples in a `.raw` file next to it.
+use crate::error::{CarvingError, Result};
+use crate::volume::{Volume, Voxel};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// What the samples of a NRRD volume are
+struct Samples<'a> {
+    /// the NRRD type of each value
+    value_type: &'a str,
+    /// the number of values per voxel and their NRRD kind, if there's more
+    /// than one
+    vector: Option<(usize, &'a str)>,
+}
+
+/// Write a NRRD volume the size of `volume` to `filename`, with the bytes of
+/// every voxel's values in storage order
+fn write<const N: usize>(
+    volume: &Volume,
+    filename: &str,
+    samples: Samples,
+    values: impl Iterator<Item = [u8; N]>,
+) -> Result<()> {
+    let origin = volume.voxel_to_position(0, 0, 0);
+    let s = volume.voxel_size;
+
+    // Several values per voxel go on an extra, fastest axis that isn't in
+    // space
+    let (dimension, size, direction, center, kinds) = match samples.vector {
+        Some((count, kind)) => (
+            4,
+            format!("{} ", count),
+            "none ",
+            "??? ",
+            format!("kinds: {} domain domain domain\n", kind),
+        ),
+        None => (3, String::new(), "", "", String::new()),
+    };
+    // Voxel indices grow towards +x, -y and -z in scene space
+    let mut header = format!(
+        "NRRD0004
+type: {}
+dimension: {}
+sizes: {}{} {} {}
+space dimension: 3
+space directions: {}({},0,0) (0,{},0) (0,0,{})
+space origin: ({},{},{})
+centers: {}cell cell cell
+{}endian: little
+encoding: raw
+",
+        samples.value_type,
+        dimension,
+        size,
+        volume.width,
+        volume.height,
+        volume.depth,
+        direction,
+        s,
+        -s,
+        -s,
+        origin.x,
+        origin.y,
+        origin.z,
+        center,
+        kinds,
+    );
+
+    let path = Path::new(filename);
+    let detached = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("nhdr"));
+    let data_path = if detached {
+        let data_path = path.with_extension("raw");
+        let data_file = data_path.file_name().unwrap_or_default().to_string_lossy();
+        header.push_str(&format!("data file: {}\n", data_file));
+        fs::write(filename, &header).map_err(|e| CarvingError::io(filename, e))?;
+        data_path
+    } else {
+        // An attached header ends with an empty line
+        header.push('\n');
+        path.to_path_buf()
+    };
+
+    let write = || -> io::Result<()> {
+        let mut out = BufWriter::new(fs::File::create(&data_path)?);
+        if !detached {
+            out.write_all(header.as_bytes())?;
+        }
+        for value in values {
+            out.write_all(&value)?;
+        }
+        out.flush()
+    };
+    write().map_err(|e| CarvingError::io(&data_path, e))
+}
+
+/// write a scalar field with one value per voxel of `volume` (indexed like the
+/// volume, e.g. `Occupancy::probabilities`) to `filename` as a raw little
+/// endian float NRRD volume
+pub fn write_nrrd(volume: &Volume, values: &[f32], filename: &str) -> Result<()> {
+    let samples = Samples {
+        value_type: "float",
+        vector: None,
+    };
+    write(
+        volume,
+        filename,
+        samples,
+        values.iter().map(|v| v.to_le_bytes()),
+    )
+}
+
+/// write the voxels of `volume` to `filename` as an 8 bit RGBA NRRD volume.
+/// The color channels hold the color of colored voxels and are 0 otherwise,
+/// and alpha holds the occupancy, 255 for voxels that are still there and 0
+/// for carved ones.
+pub fn write_volume_nrrd(volume: &Volume, filename: &str) -> Result<()> {
+    let samples = Samples {
+        value_type: "uchar",
+        vector: Some((4, "RGBA-color")),
+    };
+    let values = volume.voxels().map(|(_, voxel)| match voxel {
+        Voxel::Carved => [0, 0, 0, 0],
+        Voxel::Untouched => [0, 0, 0, 255],
+        Voxel::Colored(color) => {
+            let [r, g, b] = color.to_rgb8();
+            [r, g, b, 255]
+        }
+    });
+    write(volume, filename, samples, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::Color;
+    use nalgebra_glm as glm;
+
+    /// a 2x2x2 volume with one carved, one colored and six untouched voxels
+    fn volume() -> Volume {
+        let mut volume =
+            Volume::new(0.5, glm::vec3(-0.5, 1.0, 0.5), glm::vec3(0.5, 0.0, -0.5)).unwrap();
+        volume.set(0, 0, 0, Voxel::Carved);
+        volume.set(1, 1, 1, Voxel::Colored(Color::from_rgb8([10, 20, 30])));
+        volume
+    }
+
+    fn filename(name: &str, extension: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "space-carving-{}-{}.{}",
+                name,
+                std::process::id(),
+                extension
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn writes_rgba_volumes() {
+        let filename = filename("rgba", "nrrd");
+        write_volume_nrrd(&volume(), &filename).unwrap();
+        let data = fs::read(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+
+        let header = "NRRD0004
+type: uchar
+dimension: 4
+sizes: 4 2 2 2
+space dimension: 3
+space directions: none (0.5,0,0) (0,-0.5,0) (0,0,-0.5)
+space origin: (-0.25,0.75,0.25)
+centers: ??? cell cell cell
+kinds: RGBA-color domain domain domain
+endian: little
+encoding: raw
+
+";
+        assert_eq!(String::from_utf8_lossy(&data[..header.len()]), header);
+        let mut samples = [[0, 0, 0, 255]; 8];
+        samples[0] = [0, 0, 0, 0];
+        samples[7] = [10, 20, 30, 255];
+        assert_eq!(data[header.len()..], samples.concat()[..]);
+    }
+
+    #[test]
+    fn writes_detached_float_volumes() {
+        let header_name = filename("occupancy", "nhdr");
+        let values: Vec<f32> = (0..8).map(|i| i as f32 / 8.0).collect();
+        write_nrrd(&volume(), &values, &header_name).unwrap();
+
+        let raw_name = Path::new(&header_name).with_extension("raw");
+        let header = fs::read_to_string(&header_name).unwrap();
+        let raw = fs::read(&raw_name).unwrap();
+        fs::remove_file(&header_name).unwrap();
+        fs::remove_file(&raw_name).unwrap();
+
+        assert!(header.starts_with("NRRD0004\ntype: float\ndimension: 3\nsizes: 2 2 2\n"));
+        assert!(header.contains("space directions: (0.5,0,0) (0,-0.5,0) (0,0,-0.5)\n"));
+        assert!(header.ends_with(&format!(
+            "data file: {}\n",
+            raw_name.file_name().unwrap().to_string_lossy()
+        )));
+        let read: Vec<f32> = raw
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(read, values);
+    }
+}
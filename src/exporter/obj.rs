@@ -0,0 +1,138 @@
+use crate::error::{CarvingError, Result};
+use crate::mesh::Mesh;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// the name of the one material in the material library
+const MATERIAL: &str = "carved";
+
+/// A texture with one texel for every distinct face color, laid out in rows
+/// in a square image
+struct Palette {
+    colors: Vec<[u8; 3]>,
+    /// the texel of every face
+    faces: Vec<usize>,
+}
+impl Palette {
+    fn new(mesh: &Mesh) -> Self {
+        let mut texels = HashMap::new();
+        let mut colors = vec![];
+        let faces = mesh
+            .face_colors
+            .iter()
+            .map(|color| {
+                let rgb = color.to_rgb8();
+                *texels.entry(rgb).or_insert_with(|| {
+                    colors.push(rgb);
+                    colors.len() - 1
+                })
+            })
+            .collect();
+        Palette { colors, faces }
+    }
+    /// the width and height of the texture
+    fn side(&self) -> u32 {
+        (self.colors.len() as f64).sqrt().ceil().max(1.0) as u32
+    }
+    /// the texture coordinates of the middle of texel `i`. `obj` puts the
+    /// origin at the bottom left of the image.
+    fn uv(&self, i: usize) -> (f32, f32) {
+        let side = self.side() as usize;
+        let (x, y) = (i % side, i / side);
+        let scale = 1.0 / side as f32;
+        ((x as f32 + 0.5) * scale, 1.0 - (y as f32 + 0.5) * scale)
+    }
+    fn save(&self, filename: &Path) -> Result<()> {
+        let side = self.side();
+        let mut texture = image::RgbImage::new(side, side);
+        for (i, &color) in self.colors.iter().enumerate() {
+            let i = i as u32;
+            texture.put_pixel(i % side, i / side, image::Rgb(color));
+        }
+        texture
+            .save(filename)
+            .map_err(|e| CarvingError::io(filename, io::Error::other(e)))
+    }
+}
+
+/// write a mesh to `filename` as a Wavefront `obj` file, with its vertex
+/// normals if it has any. Vertex colors are written after the position of
+/// each vertex, which Meshlab, Blender and most other tools understand. `obj`
+/// has no way to color faces by itself, so face colors are baked into a
+/// texture instead, with one texel per color, saved as a `.png` next to
+/// `filename` along with an `.mtl` material library that uses it.
+pub fn write_obj(mesh: &Mesh, filename: &str) -> Result<()> {
+    let has_normals = !mesh.normals.is_empty();
+    let has_vertex_colors = !mesh.vertex_colors.is_empty();
+    let palette = (!mesh.face_colors.is_empty()).then(|| Palette::new(mesh));
+
+    let path = Path::new(filename);
+    let library = path.with_extension("mtl");
+    let texture = path.with_extension("png");
+    let file_name = |p: &Path| {
+        p.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    if let Some(palette) = &palette {
+        palette.save(&texture)?;
+        let material = format!(
+            "newmtl {}\nKa 1 1 1\nKd 1 1 1\nKs 0 0 0\nillum 1\nmap_Kd {}\n",
+            MATERIAL,
+            file_name(&texture)
+        );
+        fs::write(&library, material).map_err(|e| CarvingError::io(&library, e))?;
+    }
+
+    let file = fs::File::create(filename).map_err(|e| CarvingError::io(filename, e))?;
+    let mut out = BufWriter::new(file);
+    let write = |out: &mut BufWriter<fs::File>| -> io::Result<()> {
+        writeln!(out, "# space-carving")?;
+        if palette.is_some() {
+            writeln!(out, "mtllib {}", file_name(&library))?;
+        }
+        for (i, position) in mesh.positions.iter().enumerate() {
+            write!(out, "v {} {} {}", position.x, position.y, position.z)?;
+            if has_vertex_colors {
+                let color = mesh.vertex_colors[i];
+                write!(out, " {} {} {}", color.r, color.g, color.b)?;
+            }
+            writeln!(out)?;
+        }
+        if has_normals {
+            for normal in &mesh.normals {
+                writeln!(out, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+            }
+        }
+        if let Some(palette) = &palette {
+            for i in 0..palette.colors.len() {
+                let (u, v) = palette.uv(i);
+                writeln!(out, "vt {} {}", u, v)?;
+            }
+            writeln!(out, "usemtl {}", MATERIAL)?;
+        }
+
+        // obj indices start at 1, every vertex has the normal with the same
+        // index, and every corner of a face the texture coordinate of the
+        // face's color
+        for (i, face) in mesh.faces().enumerate() {
+            let texel = palette.as_ref().map(|p| p.faces[i] + 1);
+            write!(out, "f")?;
+            for index in face {
+                let v = index + 1;
+                match (texel, has_normals) {
+                    (Some(t), true) => write!(out, " {}/{}/{}", v, t, v)?,
+                    (Some(t), false) => write!(out, " {}/{}", v, t)?,
+                    (None, true) => write!(out, " {}//{}", v, v)?,
+                    (None, false) => write!(out, " {}", v)?,
+                }
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    };
+    write(&mut out).map_err(|e| CarvingError::io(filename, e))
+}
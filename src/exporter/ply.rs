@@ -0,0 +1,438 @@
+use crate::error::{CarvingError, Result};
+use crate::mesh::Mesh;
+use crate::volume::{Color, Volume, Voxel};
+use nalgebra_glm as glm;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+/// How the body of a `ply` file is stored
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    /// human readable text, one element per line
+    #[default]
+    Ascii,
+    /// `binary_little_endian`, several times smaller and faster to read and
+    /// write
+    Binary,
+}
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Encoding::Ascii),
+            "binary" => Ok(Encoding::Binary),
+            _ => Err(format!(
+                "unknown ply encoding `{}`, expected one of: ascii, binary",
+                s
+            )),
+        }
+    }
+}
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::Ascii => write!(f, "ascii"),
+            Encoding::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+/// Streams a `ply` file to disk one value at a time, in either encoding. In
+/// ascii, values are separated by spaces and every element ends with `end`.
+struct PlyWriter {
+    out: BufWriter<fs::File>,
+    encoding: Encoding,
+    /// true if nothing has been written on the current ascii line yet
+    line_start: bool,
+}
+impl PlyWriter {
+    /// create `filename` and write the header, with `elements` holding the
+    /// element and property lines
+    fn create(filename: &str, encoding: Encoding, elements: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(fs::File::create(filename)?);
+        let format = match encoding {
+            Encoding::Ascii => "ascii",
+            Encoding::Binary => "binary_little_endian",
+        };
+        write!(out, "ply\nformat {} 1.0\n{}end_header\n", format, elements)?;
+        Ok(PlyWriter {
+            out,
+            encoding,
+            line_start: true,
+        })
+    }
+    fn separate(&mut self) -> io::Result<()> {
+        if !self.line_start {
+            self.out.write_all(b" ")?;
+        }
+        self.line_start = false;
+        Ok(())
+    }
+    fn float(&mut self, value: f32) -> io::Result<()> {
+        match self.encoding {
+            Encoding::Ascii => {
+                self.separate()?;
+                write!(self.out, "{}", value)
+            }
+            Encoding::Binary => self.out.write_all(&value.to_le_bytes()),
+        }
+    }
+    fn uchar(&mut self, value: u8) -> io::Result<()> {
+        match self.encoding {
+            Encoding::Ascii => {
+                self.separate()?;
+                write!(self.out, "{}", value)
+            }
+            Encoding::Binary => self.out.write_all(&[value]),
+        }
+    }
+    fn int(&mut self, value: u32) -> io::Result<()> {
+        match self.encoding {
+            Encoding::Ascii => {
+                self.separate()?;
+                write!(self.out, "{}", value)
+            }
+            Encoding::Binary => self.out.write_all(&(value as i32).to_le_bytes()),
+        }
+    }
+    fn color(&mut self, color: Color) -> io::Result<()> {
+        for channel in color.to_rgb8() {
+            self.uchar(channel)?;
+        }
+        Ok(())
+    }
+    /// write a `list uchar int` property
+    fn list(&mut self, indices: &[u32]) -> io::Result<()> {
+        self.uchar(indices.len() as u8)?;
+        for &index in indices {
+            self.int(index)?;
+        }
+        Ok(())
+    }
+    /// finish the current element
+    fn end(&mut self) -> io::Result<()> {
+        if self.encoding == Encoding::Ascii {
+            self.out.write_all(b"\n")?;
+            self.line_start = true;
+        }
+        Ok(())
+    }
+    fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// the center and color of every voxel `write_ply` draws as a cube, in
+/// storage order. Untouched voxels are only drawn if they're on the surface of
+/// the volume.
+fn cubes(volume: &Volume) -> impl Iterator<Item = (glm::Vec3, Color)> + '_ {
+    volume
+        .voxels()
+        .filter_map(move |((x, y, z), voxel)| match voxel {
+            Voxel::Colored(color) => Some((volume.voxel_to_position(x, y, z), color)),
+            Voxel::Untouched if matches!(volume.voxel_visible(x, y, z), Ok(true)) => {
+                Some((volume.voxel_to_position(x, y, z), Color::new(1., 0., 1.)))
+            }
+            _ => None,
+        })
+}
+
+/// write the carved volume to `filename` as a `ply` mesh with one colored cube
+/// per surface voxel. Untouched surface voxels are drawn in magenta. The cubes
+/// are counted for the header first and then written as they're found, so
+/// they're never all held in memory.
+pub fn write_ply(volume: &Volume, filename: &str, encoding: Encoding) -> Result<()> {
+    let count = cubes(volume).count();
+
+    let elements = format!(
+        "element vertex {}
+property float x
+property float y
+property float z
+property uchar diffuse_red
+property uchar diffuse_green
+property uchar diffuse_blue
+element face {}
+property list uchar int vertex_indices
+",
+        count * 8,
+        count * 6,
+    );
+
+    let write = || -> io::Result<()> {
+        let mut out = PlyWriter::create(filename, encoding, &elements)?;
+
+        let s = volume.voxel_size / 2.0;
+        for (position, color) in cubes(volume) {
+            let (x, y, z) = (position.x, position.y, position.z);
+            let corners = [
+                (x - s, y - s, z - s),
+                (x + s, y - s, z - s),
+                (x - s, y + s, z - s),
+                (x + s, y + s, z - s),
+                (x - s, y - s, z + s),
+                (x + s, y - s, z + s),
+                (x - s, y + s, z + s),
+                (x + s, y + s, z + s),
+            ];
+            for (x, y, z) in corners {
+                out.float(x)?;
+                out.float(y)?;
+                out.float(z)?;
+                out.color(color)?;
+                out.end()?;
+            }
+        }
+
+        // Corners in the order above, named from a looking forward
+        // perspective (towards negative z)
+        let back_bottom_left = 0;
+        let back_bottom_right = 1;
+        let back_top_left = 2;
+        let back_top_right = 3;
+        let front_bottom_left = 4;
+        let front_bottom_right = 5;
+        let front_top_left = 6;
+        let front_top_right = 7;
+        let faces = [
+            [
+                front_top_left,
+                front_top_right,
+                back_top_right,
+                back_top_left,
+            ], // top face
+            [
+                front_bottom_left,
+                front_bottom_right,
+                back_bottom_right,
+                back_bottom_left,
+            ], // bottom face
+            [
+                front_bottom_right,
+                back_bottom_right,
+                back_top_right,
+                front_top_right,
+            ], // right face
+            [
+                front_bottom_left,
+                back_bottom_left,
+                back_top_left,
+                front_top_left,
+            ], // left face
+            [
+                front_bottom_left,
+                front_bottom_right,
+                front_top_right,
+                front_top_left,
+            ], // front face
+            [
+                back_bottom_left,
+                back_bottom_right,
+                back_top_right,
+                back_top_left,
+            ], // back face
+        ];
+        for cube in 0..count {
+            let base = cube as u32 * 8;
+            for face in faces {
+                out.list(&face.map(|corner| base + corner))?;
+                out.end()?;
+            }
+        }
+        out.finish()
+    };
+    write().map_err(|e| CarvingError::io(filename, e))
+}
+
+/// write a mesh (e.g. from `mesh::exposed_faces`) to `filename` as a `ply`
+/// file, with its vertex normals, vertex colors and face colors if it has
+/// any. A mesh without faces, like `mesh::point_cloud`, is written as a point
+/// cloud with no face element at all.
+pub fn write_mesh_ply(mesh: &Mesh, filename: &str, encoding: Encoding) -> Result<()> {
+    let has_normals = !mesh.normals.is_empty();
+    let has_vertex_colors = !mesh.vertex_colors.is_empty();
+    let has_faces = !mesh.indices.is_empty();
+    let has_face_colors = !mesh.face_colors.is_empty();
+
+    let mut elements = format!(
+        "element vertex {}
+property float x
+property float y
+property float z
+",
+        mesh.positions.len(),
+    );
+    if has_normals {
+        elements.push_str("property float nx\nproperty float ny\nproperty float nz\n");
+    }
+    if has_vertex_colors {
+        elements.push_str("property uchar red\nproperty uchar green\nproperty uchar blue\n");
+    }
+    if has_faces {
+        elements.push_str(&format!(
+            "element face {}\nproperty list uchar int vertex_indices\n",
+            mesh.face_count()
+        ));
+        if has_face_colors {
+            elements.push_str("property uchar red\nproperty uchar green\nproperty uchar blue\n");
+        }
+    }
+
+    let write = || -> io::Result<()> {
+        let mut out = PlyWriter::create(filename, encoding, &elements)?;
+        for (i, position) in mesh.positions.iter().enumerate() {
+            for value in position.iter() {
+                out.float(*value)?;
+            }
+            if has_normals {
+                for value in mesh.normals[i].iter() {
+                    out.float(*value)?;
+                }
+            }
+            if has_vertex_colors {
+                out.color(mesh.vertex_colors[i])?;
+            }
+            out.end()?;
+        }
+        for (i, face) in mesh.faces().enumerate() {
+            out.list(face)?;
+            if has_face_colors {
+                out.color(mesh.face_colors[i])?;
+            }
+            out.end()?;
+        }
+        out.finish()
+    };
+    write().map_err(|e| CarvingError::io(filename, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh;
+
+    /// a 4x2x2 volume with a few carved and colored voxels
+    fn volume() -> Volume {
+        let mut volume =
+            Volume::new(0.5, glm::vec3(-1.0, 0.5, 0.5), glm::vec3(1.0, -0.5, -0.5)).unwrap();
+        volume.set(0, 0, 0, Voxel::Carved);
+        volume.set(3, 1, 1, Voxel::Carved);
+        volume.set(1, 0, 1, Voxel::Colored(Color::new(0.2, 0.4, 0.6)));
+        volume.set(2, 1, 0, Voxel::Colored(Color::new(1.0, 0.0, 0.5)));
+        volume
+    }
+
+    fn filename(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("space-carving-{}-{}.ply", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// read back the values of every element of a ply file in either encoding,
+    /// along with its header
+    fn read(filename: &str) -> (String, Vec<Vec<f64>>) {
+        let data = fs::read(filename).unwrap();
+        fs::remove_file(filename).unwrap();
+        let end = b"end_header\n";
+        let body = data.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = String::from_utf8(data[..body].to_vec()).unwrap();
+
+        // every element's count and property types, with lists as `list`
+        let mut elements: Vec<(usize, Vec<&str>)> = vec![];
+        for line in header.lines() {
+            let words: Vec<&str> = line.split(' ').collect();
+            match words[..] {
+                ["element", _, count] => elements.push((count.parse().unwrap(), vec![])),
+                ["property", "list", "uchar", "int", _] => {
+                    elements.last_mut().unwrap().1.push("list")
+                }
+                ["property", kind, _] => elements.last_mut().unwrap().1.push(kind),
+                _ => {}
+            }
+        }
+
+        let mut rows = vec![];
+        if header.contains("format ascii") {
+            let text = String::from_utf8(data[body..].to_vec()).unwrap();
+            for line in text.lines() {
+                rows.push(line.split(' ').map(|v| v.parse().unwrap()).collect());
+            }
+        } else {
+            let mut at = body;
+            let mut take = |n: usize| {
+                at += n;
+                &data[at - n..at]
+            };
+            for (count, properties) in &elements {
+                for _ in 0..*count {
+                    let mut row = vec![];
+                    for &kind in properties {
+                        match kind {
+                            "float" => {
+                                let b = take(4);
+                                row.push(f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64);
+                            }
+                            "uchar" => row.push(take(1)[0] as f64),
+                            _ => {
+                                let len = take(1)[0];
+                                row.push(len as f64);
+                                for _ in 0..len {
+                                    let b = take(4);
+                                    row.push(i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64);
+                                }
+                            }
+                        }
+                    }
+                    rows.push(row);
+                }
+            }
+            assert_eq!(at, data.len());
+        }
+        assert_eq!(
+            rows.len(),
+            elements.iter().map(|(count, _)| count).sum::<usize>()
+        );
+        (header, rows)
+    }
+
+    #[test]
+    fn writes_the_same_cubes_in_both_encodings() {
+        let volume = volume();
+        let (ascii, binary) = (filename("cubes-ascii"), filename("cubes-binary"));
+        write_ply(&volume, &ascii, Encoding::Ascii).unwrap();
+        write_ply(&volume, &binary, Encoding::Binary).unwrap();
+        let (ascii_header, ascii) = read(&ascii);
+        let (binary_header, binary) = read(&binary);
+
+        // Every kept voxel is on the surface of such a small volume
+        assert!(ascii_header.contains("element vertex 112\n"));
+        assert!(ascii_header.contains("element face 84\n"));
+        assert_eq!(
+            binary_header,
+            ascii_header.replace("format ascii", "format binary_little_endian")
+        );
+        assert_eq!(ascii, binary);
+
+        // The first kept voxel, (1, 0, 0), is untouched and drawn in magenta,
+        // starting with its corner at the smallest coordinates
+        assert_eq!(ascii[0], [-0.5, 0.0, 0.0, 255.0, 0.0, 255.0]);
+        assert_eq!(ascii[112], [4.0, 6.0, 7.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn writes_the_same_mesh_in_both_encodings() {
+        let mesh = mesh::exposed_faces(&volume(), false);
+        let (ascii, binary) = (filename("mesh-ascii"), filename("mesh-binary"));
+        write_mesh_ply(&mesh, &ascii, Encoding::Ascii).unwrap();
+        write_mesh_ply(&mesh, &binary, Encoding::Binary).unwrap();
+        let (_, ascii) = read(&ascii);
+        let (_, binary) = read(&binary);
+
+        assert_eq!(ascii.len(), mesh.positions.len() + mesh.face_count());
+        assert_eq!(ascii, binary);
+    }
+}
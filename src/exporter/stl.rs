@@ -0,0 +1,49 @@
+use crate::error::{CarvingError, Result};
+use crate::mesh::Mesh;
+use nalgebra_glm as glm;
+use std::fs;
+use std::io::{BufWriter, Write};
+
+/// write a mesh to `filename` as a binary `stl` file. `stl` only holds
+/// triangles, so larger faces are split into fans, and it has no colors or
+/// vertex normals, so those are left out. Each triangle's normal is worked out
+/// from its winding.
+pub fn write_stl(mesh: &Mesh, filename: &str) -> Result<()> {
+    let file = fs::File::create(filename).map_err(|e| CarvingError::io(filename, e))?;
+    let mut out = BufWriter::new(file);
+
+    let triangles: Vec<[glm::Vec3; 3]> = mesh
+        .faces()
+        .flat_map(|face| {
+            (1..face.len().saturating_sub(1)).map(move |i| {
+                [face[0], face[i], face[i + 1]].map(|index| mesh.positions[index as usize])
+            })
+        })
+        .collect();
+
+    let write = |out: &mut BufWriter<fs::File>| -> std::io::Result<()> {
+        let mut header = [0u8; 80];
+        let name = b"space-carving";
+        header[..name.len()].copy_from_slice(name);
+        out.write_all(&header)?;
+        out.write_all(&(triangles.len() as u32).to_le_bytes())?;
+
+        for [a, b, c] in &triangles {
+            let normal = glm::cross(&(b - a), &(c - a));
+            let normal = if glm::length(&normal) > 0.0 {
+                glm::normalize(&normal)
+            } else {
+                normal
+            };
+            for vector in [&normal, a, b, c] {
+                for value in vector.iter() {
+                    out.write_all(&value.to_le_bytes())?;
+                }
+            }
+            // the attribute byte count, which is unused
+            out.write_all(&0u16.to_le_bytes())?;
+        }
+        out.flush()
+    };
+    write(&mut out).map_err(|e| CarvingError::io(filename, e))
+}
@@ -0,0 +1,344 @@
+//! MagicaVoxel `.vox` files. Every voxel that's still there becomes a voxel
+//! of the model, colored from a palette of at most 255 colors that the
+//! volume's colors are quantized to. A model can be at most 256 voxels along
+//! each axis, so larger volumes are split into several models, placed next to
+//! each other with a scene graph.
+use crate::error::{CarvingError, Result};
+use crate::volume::{Volume, Voxel};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// the most voxels a model can have along each axis
+const MAX_SIZE: usize = 256;
+/// the number of colors in a palette. Index 0 means an empty voxel.
+const PALETTE_SIZE: usize = 255;
+/// the color of untouched voxels, like in the meshes
+const UNTOUCHED: [u8; 3] = [255, 0, 255];
+
+/// Quantize `colors` (with the number of voxels of each) to at most
+/// `PALETTE_SIZE` colors with median cut: the group of colors that spans the
+/// largest range in one channel is split at its median in that channel until
+/// there are enough groups, and each group becomes the average of its colors.
+/// Returns the palette and the palette index of every color.
+fn quantize(colors: HashMap<[u8; 3], usize>) -> (Vec<[u8; 3]>, HashMap<[u8; 3], u8>) {
+    let mut groups = vec![colors.into_iter().collect::<Vec<_>>()];
+
+    while groups.len() < PALETTE_SIZE {
+        // the channel and range of the widest group
+        let widest = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, group)| {
+                (0..3).map(move |channel| {
+                    let values = group.iter().map(|(color, _)| color[channel]);
+                    let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                    (range, i, channel)
+                })
+            })
+            .max();
+        let (i, channel) = match widest {
+            Some((range, i, channel)) if range > 0 => (i, channel),
+            _ => break,
+        };
+
+        // Split where half the voxels are on either side, keeping at least
+        // one color in each half
+        let group = &mut groups[i];
+        group.sort_by_key(|(color, _)| color[channel]);
+        let total: usize = group.iter().map(|(_, count)| count).sum();
+        let mut seen = 0;
+        let mut split = group.len() - 1;
+        for (j, (_, count)) in group.iter().enumerate() {
+            seen += count;
+            if seen * 2 >= total {
+                split = j + 1;
+                break;
+            }
+        }
+        let split = split.clamp(1, group.len() - 1);
+        let upper = group.split_off(split);
+        groups.push(upper);
+    }
+
+    let mut palette = Vec::with_capacity(groups.len());
+    let mut indices = HashMap::new();
+    for (i, group) in groups.iter().enumerate() {
+        let total: usize = group.iter().map(|(_, count)| count).sum();
+        let mean = |channel: usize| {
+            let sum: usize = group
+                .iter()
+                .map(|(color, count)| color[channel] as usize * count)
+                .sum();
+            ((sum as f64 / total.max(1) as f64).round()) as u8
+        };
+        palette.push([mean(0), mean(1), mean(2)]);
+        for (color, _) in group {
+            indices.insert(*color, (i + 1) as u8);
+        }
+    }
+    (palette, indices)
+}
+
+/// add a chunk with the given id, contents and children to `out`
+fn chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend(id);
+    out.extend((content.len() as u32).to_le_bytes());
+    out.extend((children.len() as u32).to_le_bytes());
+    out.extend(content);
+    out.extend(children);
+}
+
+/// the bytes of an `i32`, as every integer in a `.vox` file is stored
+fn int(value: i32) -> [u8; 4] {
+    value.to_le_bytes()
+}
+
+/// the bytes of a dictionary of string keys and values
+fn dictionary(entries: &[(&str, String)]) -> Vec<u8> {
+    let mut out = int(entries.len() as i32).to_vec();
+    for (key, value) in entries {
+        for string in [key.as_bytes(), value.as_bytes()] {
+            out.extend(int(string.len() as i32));
+            out.extend(string);
+        }
+    }
+    out
+}
+
+/// add a transform node that moves its child by `translation` to `out`
+fn transform(out: &mut Vec<u8>, id: i32, child: i32, layer: i32, translation: Option<[i32; 3]>) {
+    let mut content = int(id).to_vec();
+    content.extend(dictionary(&[]));
+    content.extend(int(child));
+    // a reserved id, which is always -1
+    content.extend(int(-1));
+    content.extend(int(layer));
+    // one frame, with the translation if there is one
+    content.extend(int(1));
+    let frame = translation.map(|[x, y, z]| ("_t", format!("{} {} {}", x, y, z)));
+    content.extend(dictionary(frame.as_slice()));
+    chunk(out, b"nTRN", &content, &[]);
+}
+
+/// write every voxel of `volume` that hasn't been carved to `filename` as a
+/// MagicaVoxel `.vox` file. Untouched voxels are magenta, like in the meshes.
+/// MagicaVoxel's z axis points up, so the volume is turned to put scene +y
+/// up.
+pub fn write_vox(volume: &Volume, filename: &str) -> Result<()> {
+    // Voxel indices grow towards +x, -y and -z in scene space, so the scene
+    // axes (x, -z, y) that MagicaVoxel wants are the index axes (x, z, -y)
+    let size = [volume.width, volume.depth, volume.height];
+    let model_size = |block: &[usize; 3]| {
+        [0, 1, 2].map(|axis| (size[axis] - block[axis] * MAX_SIZE).min(MAX_SIZE))
+    };
+    let position = |(x, y, z): (usize, usize, usize)| [x, z, volume.height - 1 - y];
+    let color = |voxel: Voxel| match voxel {
+        Voxel::Colored(color) => Some(color.to_rgb8()),
+        Voxel::Untouched => Some(UNTOUCHED),
+        Voxel::Carved => None,
+    };
+
+    let mut counts = HashMap::new();
+    for (_, voxel) in volume.voxels() {
+        if let Some(rgb) = color(voxel) {
+            *counts.entry(rgb).or_insert(0) += 1;
+        }
+    }
+    let (palette, indices) = quantize(counts);
+
+    // Sort the voxels into the models that cover each block of the volume
+    let mut models: BTreeMap<[usize; 3], Vec<[u8; 4]>> = BTreeMap::new();
+    for (coords, voxel) in volume.voxels() {
+        if let Some(rgb) = color(voxel) {
+            let p = position(coords);
+            let block = p.map(|i| i / MAX_SIZE);
+            let [x, y, z] = p.map(|i| (i % MAX_SIZE) as u8);
+            models
+                .entry(block)
+                .or_default()
+                .push([x, y, z, indices[&rgb]]);
+        }
+    }
+    if models.is_empty() {
+        models.insert([0, 0, 0], vec![]);
+    }
+
+    let mut children = vec![];
+    for (block, voxels) in &models {
+        let content: Vec<u8> = model_size(block)
+            .iter()
+            .flat_map(|&s| int(s as i32))
+            .collect();
+        chunk(&mut children, b"SIZE", &content, &[]);
+        let mut content = int(voxels.len() as i32).to_vec();
+        content.extend(voxels.iter().flatten());
+        chunk(&mut children, b"XYZI", &content, &[]);
+    }
+
+    // The scene graph: a root transform and a group holding a transform and
+    // a shape for every model. MagicaVoxel places a model by its center.
+    transform(&mut children, 0, 1, -1, None);
+    let shapes: Vec<i32> = (0..models.len() as i32).map(|i| 2 + 2 * i).collect();
+    let mut content = int(1).to_vec();
+    content.extend(dictionary(&[]));
+    content.extend(int(shapes.len() as i32));
+    content.extend(shapes.iter().flat_map(|&id| int(id)));
+    chunk(&mut children, b"nGRP", &content, &[]);
+    for (i, block) in models.keys().enumerate() {
+        let model_size = model_size(block);
+        let translation =
+            [0, 1, 2].map(|axis| (block[axis] * MAX_SIZE + model_size[axis] / 2) as i32);
+        let id = shapes[i];
+        transform(&mut children, id, id + 1, 0, Some(translation));
+
+        let mut content = int(id + 1).to_vec();
+        content.extend(dictionary(&[]));
+        content.extend(int(1));
+        content.extend(int(i as i32));
+        content.extend(dictionary(&[]));
+        chunk(&mut children, b"nSHP", &content, &[]);
+    }
+
+    // Palette entry i is color index i + 1, and unused entries are black
+    let mut content = vec![0; 4 * 256];
+    for (i, rgb) in palette.iter().enumerate() {
+        content[4 * i..4 * i + 4].copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+    }
+    chunk(&mut children, b"RGBA", &content, &[]);
+
+    let mut out = b"VOX ".to_vec();
+    out.extend(int(150));
+    chunk(&mut out, b"MAIN", &[], &children);
+    fs::write(filename, out).map_err(|e| CarvingError::io(filename, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::Color;
+    use nalgebra_glm as glm;
+
+    /// write `volume` as a `.vox` file and read back the chunks inside MAIN as
+    /// `(id, content)`
+    fn chunks(name: &str, volume: &Volume) -> Vec<([u8; 4], Vec<u8>)> {
+        let filename = std::env::temp_dir()
+            .join(format!("space-carving-{}-{}.vox", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        write_vox(volume, &filename).unwrap();
+        let data = fs::read(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+
+        let word =
+            |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        assert_eq!(&data[..4], b"VOX ");
+        assert_eq!(word(4), 150);
+        assert_eq!(&data[8..12], b"MAIN");
+        assert_eq!(20 + word(16) as usize, data.len());
+
+        let mut chunks = vec![];
+        let mut at = 20;
+        while at < data.len() {
+            let id = [data[at], data[at + 1], data[at + 2], data[at + 3]];
+            let length = word(at + 4) as usize;
+            assert_eq!(word(at + 8), 0);
+            chunks.push((id, data[at + 12..at + 12 + length].to_vec()));
+            at += 12 + length;
+        }
+        chunks
+    }
+
+    fn ints(content: &[u8]) -> Vec<i32> {
+        content
+            .chunks(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn writes_kept_voxels_with_scene_y_up() {
+        // A 2x4x2 volume with one untouched and one colored voxel left
+        let mut volume =
+            Volume::new(1.0, glm::vec3(0.0, 4.0, 2.0), glm::vec3(2.0, 0.0, 0.0)).unwrap();
+        for i in 0..volume.len() {
+            let (x, y, z) = volume.coords(i);
+            volume.set(x, y, z, Voxel::Carved);
+        }
+        volume.set(1, 0, 0, Voxel::Untouched);
+        volume.set(0, 3, 1, Voxel::Colored(Color::from_rgb8([10, 20, 30])));
+        let chunks = chunks("small", &volume);
+
+        let ids: Vec<&[u8]> = chunks.iter().map(|(id, _)| &id[..]).collect();
+        assert_eq!(
+            ids,
+            [
+                &b"SIZE"[..],
+                b"XYZI",
+                b"nTRN",
+                b"nGRP",
+                b"nTRN",
+                b"nSHP",
+                b"RGBA"
+            ]
+        );
+        assert_eq!(ints(&chunks[0].1), [2, 2, 4]);
+
+        // The top voxel in the scene is at the top of the model, and each
+        // voxel has its own palette entry
+        let xyzi = &chunks[1].1;
+        assert_eq!(ints(&xyzi[..4]), [2]);
+        let palette = &chunks[6].1;
+        let mut voxels = vec![];
+        for voxel in xyzi[4..].chunks(4) {
+            let entry = 4 * (voxel[3] as usize - 1);
+            voxels.push((
+                [voxel[0], voxel[1], voxel[2]],
+                palette[entry..entry + 4].to_vec(),
+            ));
+        }
+        voxels.sort();
+        assert_eq!(
+            voxels,
+            [
+                ([0, 1, 0], vec![10, 20, 30, 255]),
+                ([1, 0, 3], vec![255, 0, 255, 255])
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_large_volumes_into_models() {
+        // 300 voxels along x, in 300 different shades
+        let mut volume =
+            Volume::new(1.0, glm::vec3(0.0, 2.0, 2.0), glm::vec3(300.0, 0.0, 0.0)).unwrap();
+        for i in 0..volume.len() {
+            let (x, y, z) = volume.coords(i);
+            let shade = Color::new(x as f32 / 300.0, 0.5, 0.5);
+            volume.set(x, y, z, Voxel::Colored(shade));
+        }
+        let chunks = chunks("large", &volume);
+
+        let sizes: Vec<Vec<i32>> = chunks
+            .iter()
+            .filter(|(id, _)| id == b"SIZE")
+            .map(|(_, content)| ints(content))
+            .collect();
+        assert_eq!(sizes, [[256, 2, 2], [44, 2, 2]]);
+        let voxels: usize = chunks
+            .iter()
+            .filter(|(id, _)| id == b"XYZI")
+            .map(|(_, content)| ints(&content[..4])[0] as usize)
+            .sum();
+        assert_eq!(voxels, volume.len());
+
+        // Median cut leaves at most 255 colors, with index 0 kept for empty
+        // voxels
+        let (palette, indices) = quantize(
+            (0..300)
+                .map(|i| ([(i % 256) as u8, (i / 256) as u8, 0], 1))
+                .collect(),
+        );
+        assert_eq!(palette.len(), PALETTE_SIZE);
+        assert!(indices.values().all(|&i| i >= 1));
+    }
+}
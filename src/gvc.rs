@@ -0,0 +1,154 @@
+//! Generalized Voxel Coloring, from Culbertson, Malzbender and Slabaugh's
+//! "Generalized Voxel Coloring".
+//!
+//! Rather than approximating visibility by only considering cameras on one
+//! side of a sweep plane, GVC renders the current surface voxels into an item
+//! buffer for every view. Each pixel of an item buffer records which surface
+//! voxel is nearest to the camera along that pixel's ray, so a voxel is only
+//! checked against the pixels of the views that actually see it. This works
+//! for any camera placement, including rings of cameras that surround the
+//! object.
+use crate::brdf::ConsistencyCheck;
+use crate::carve::Verdict;
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::{Volume, Voxel};
+use image::GenericImageView;
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+
+/// Marks an item buffer pixel that no surface voxel projects to
+const EMPTY: u32 = u32::MAX;
+
+/// For every pixel of a view, the index (into the surface voxel list) and
+/// distance of the nearest surface voxel that projects onto it
+struct ItemBuffer {
+    width: usize,
+    items: Vec<u32>,
+    depths: Vec<f32>,
+}
+impl ItemBuffer {
+    fn new(view: &View) -> Self {
+        let size = view.img.width() as usize * view.img.height() as usize;
+        ItemBuffer {
+            width: view.img.width() as usize,
+            items: vec![EMPTY; size],
+            depths: vec![f32::INFINITY; size],
+        }
+    }
+    /// draw `item` at `depth` onto the given pixels, keeping whichever item
+    /// is closest to the camera at each pixel
+    fn draw(&mut self, item: u32, depth: f32, pixels: &[(u32, u32)]) {
+        for &(x, y) in pixels {
+            let i = y as usize * self.width + x as usize;
+            if depth < self.depths[i] {
+                self.depths[i] = depth;
+                self.items[i] = item;
+            }
+        }
+    }
+    fn get(&self, x: u32, y: u32) -> u32 {
+        self.items[y as usize * self.width + x as usize]
+    }
+}
+
+/// all uncarved voxels with at least one carved (or out of bounds) neighbor
+fn surface_voxels(volume: &Volume) -> Result<Vec<(usize, usize, usize)>> {
+    let mut surface = vec![];
+    for ((x, y, z), voxel) in volume.voxels() {
+        if voxel != Voxel::Carved && volume.voxel_visible(x, y, z)? {
+            surface.push((x, y, z));
+        }
+    }
+    Ok(surface)
+}
+
+/// Given an uncarved volume and a set of views, carve the volume so it is
+/// consistent with the views using exact per-view visibility.
+///
+/// Each pass renders the surface into fresh item buffers and checks every
+/// surface voxel against the pixels it owns. Carving a voxel can only make
+/// other voxels more visible, so using the buffers from the start of the pass
+/// never wrongly carves a voxel; any newly exposed views are picked up on the
+/// next pass. Passes repeat until nothing is carved.
+pub fn carve(volume: &mut Volume, views: &[View], check: &dyn ConsistencyCheck) -> Result<()> {
+    let shared: Vec<&View> = views.iter().collect();
+    let mut total_carved = 0;
+
+    for pass in 1.. {
+        let surface = surface_voxels(volume)?;
+
+        let item_buffers: Vec<ItemBuffer> = views
+            .par_iter()
+            .map(|view| {
+                let mut buffer = ItemBuffer::new(view);
+                for (i, &(x, y, z)) in surface.iter().enumerate() {
+                    let depth =
+                        glm::distance(&view.camera.center(), &volume.voxel_to_position(x, y, z));
+                    buffer.draw(
+                        i as u32,
+                        depth,
+                        &view.footprint(&volume.voxel_corners(x, y, z)),
+                    );
+                }
+                buffer
+            })
+            .collect();
+
+        // Every voxel is checked against the item buffers from the start of the
+        // pass, so the voxels can be checked in parallel
+        let verdicts = surface
+            .par_iter()
+            .enumerate()
+            .map(|(i, &(x, y, z))| {
+                // Gather the pixels this voxel is the nearest surface voxel for
+                let corners = volume.voxel_corners(x, y, z);
+                let position = volume.voxel_to_position(x, y, z);
+                let mut samples = vec![];
+                for (v, (view, buffer)) in views.iter().zip(item_buffers.iter()).enumerate() {
+                    let scene_to_camera = view.camera.center() - position;
+                    for (px, py) in view.footprint(&corners) {
+                        if buffer.get(px, py) == i as u32 {
+                            samples.push(view.sample(v, px, py, scene_to_camera));
+                        }
+                    }
+                }
+
+                // No view sees this voxel, so there's no evidence to carve it with
+                if samples.is_empty() {
+                    return Ok(Verdict::Unseen);
+                }
+
+                Ok(match check.consistent(&samples, &shared)? {
+                    Some(color) => Verdict::Keep(color),
+                    None => Verdict::Carve,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut carved_this_pass = 0;
+        for (&(x, y, z), verdict) in surface.iter().zip(verdicts) {
+            match verdict {
+                Verdict::Carve => {
+                    carved_this_pass += 1;
+                    volume.set(x, y, z, Voxel::Carved);
+                }
+                Verdict::Keep(color) => {
+                    volume.set(x, y, z, Voxel::Colored(color));
+                }
+                Verdict::Unseen => {}
+            }
+        }
+
+        println!("Carved {} voxels on GVC pass {}", carved_this_pass, pass);
+
+        if carved_this_pass == 0 {
+            break;
+        }
+        total_carved += carved_this_pass;
+    }
+
+    println!("Carved {} voxels", total_carved);
+
+    Ok(())
+}
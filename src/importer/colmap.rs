@@ -0,0 +1,729 @@
+//! COLMAP sparse models, as written by `colmap mapper` (binary) or
+//! `colmap model_converter --output_type TXT` (text). A model is a folder with
+//! `cameras`, `images` and `points3D` files, in either `.bin` or `.txt` form.
+//! The binary files are read if both exist, like COLMAP does.
+//!
+//! See <https://colmap.github.io/format.html> for the layout of the files.
+use crate::error::{CarvingError, Result};
+use crate::view::{CameraData, Distortion};
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// the share of points dropped at either end of each axis when fitting a
+/// bounding box to the sparse points, since there are always a few far off
+/// mismatches
+const OUTLIER_FRACTION: f32 = 0.01;
+/// how much the box around the sparse points is grown on every side, as a
+/// fraction of its size, since the sparse points rarely reach the edges of the
+/// object
+const MARGIN: f32 = 0.1;
+
+/// The intrinsics of a COLMAP camera
+struct Intrinsics {
+    fx: f32,
+    fy: f32,
+    cx: f32,
+    cy: f32,
+    distortion: Option<Distortion>,
+}
+impl Intrinsics {
+    /// convert the parameters of one of COLMAP's camera models
+    fn new(model: &str, params: &[f64]) -> std::result::Result<Self, String> {
+        let expected = match model {
+            "SIMPLE_PINHOLE" => 3,
+            "PINHOLE" | "SIMPLE_RADIAL" | "SIMPLE_RADIAL_FISHEYE" => 4,
+            "RADIAL" | "RADIAL_FISHEYE" => 5,
+            "OPENCV" | "OPENCV_FISHEYE" => 8,
+            "FULL_OPENCV" => 12,
+            _ => {
+                return Err(format!(
+                    "unsupported camera model {}, expected one of: SIMPLE_PINHOLE, PINHOLE, SIMPLE_RADIAL, RADIAL, OPENCV, FULL_OPENCV, OPENCV_FISHEYE, SIMPLE_RADIAL_FISHEYE, RADIAL_FISHEYE",
+                    model
+                ))
+            }
+        };
+        if params.len() != expected {
+            return Err(format!(
+                "expected {} parameters for a {} camera, found {}",
+                expected,
+                model,
+                params.len()
+            ));
+        }
+
+        let p: Vec<f32> = params.iter().map(|&p| p as f32).collect();
+        let fisheye = |k1, k2, k3, k4| Distortion::Fisheye { k1, k2, k3, k4 };
+        Ok(match model {
+            "SIMPLE_PINHOLE" => Intrinsics::pinhole(p[0], p[0], p[1], p[2], None),
+            "PINHOLE" => Intrinsics::pinhole(p[0], p[1], p[2], p[3], None),
+            "SIMPLE_RADIAL" => {
+                Intrinsics::pinhole(p[0], p[0], p[1], p[2], Some(Distortion::radial(p[3], 0.0)))
+            }
+            "RADIAL" => {
+                Intrinsics::pinhole(p[0], p[0], p[1], p[2], Some(Distortion::radial(p[3], p[4])))
+            }
+            "SIMPLE_RADIAL_FISHEYE" => Intrinsics::pinhole(
+                p[0],
+                p[0],
+                p[1],
+                p[2],
+                Some(fisheye(p[3], 0.0, 0.0, 0.0)),
+            ),
+            "RADIAL_FISHEYE" => Intrinsics::pinhole(
+                p[0],
+                p[0],
+                p[1],
+                p[2],
+                Some(fisheye(p[3], p[4], 0.0, 0.0)),
+            ),
+            "OPENCV_FISHEYE" => Intrinsics::pinhole(
+                p[0],
+                p[1],
+                p[2],
+                p[3],
+                Some(fisheye(p[4], p[5], p[6], p[7])),
+            ),
+            "FULL_OPENCV" if p[9..12].iter().any(|&k| k != 0.0) => {
+                return Err(
+                    "FULL_OPENCV cameras are only supported without the rational coefficients k4, k5 and k6"
+                        .to_string(),
+                )
+            }
+            // OPENCV and FULL_OPENCV, which adds k3 (and k4 to k6)
+            _ => Intrinsics::pinhole(
+                p[0],
+                p[1],
+                p[2],
+                p[3],
+                Some(Distortion::BrownConrady {
+                    k1: p[4],
+                    k2: p[5],
+                    k3: p.get(8).copied().unwrap_or(0.0),
+                    p1: p[6],
+                    p2: p[7],
+                }),
+            ),
+        })
+    }
+    fn pinhole(fx: f32, fy: f32, cx: f32, cy: f32, distortion: Option<Distortion>) -> Self {
+        Intrinsics {
+            fx,
+            fy,
+            cx,
+            cy,
+            distortion,
+        }
+    }
+    /// the camera with these intrinsics and the pose given by COLMAP's
+    /// world-to-camera rotation quaternion `(w, x, y, z)` and translation
+    fn camera(&self, quaternion: [f64; 4], translation: [f64; 3]) -> CameraData {
+        let norm = quaternion.iter().map(|q| q * q).sum::<f64>().sqrt();
+        let [w, x, y, z] = quaternion.map(|q| (q / norm) as f32);
+        let r = [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - z * w),
+            2.0 * (x * z + y * w),
+            2.0 * (x * y + z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - x * w),
+            2.0 * (x * z - y * w),
+            2.0 * (y * z + x * w),
+            1.0 - 2.0 * (x * x + y * y),
+        ];
+        let k = [self.fx, 0.0, self.cx, 0.0, self.fy, self.cy, 0.0, 0.0, 1.0];
+        let t = translation.map(|t| t as f32);
+
+        let camera = CameraData::new(&k, &r, &t);
+        match self.distortion {
+            Some(distortion) => camera.with_distortion(distortion),
+            None => camera,
+        }
+    }
+}
+
+/// the path of a model file, preferring the binary version
+fn model_file(model: &Path, name: &str) -> Option<PathBuf> {
+    [format!("{}.bin", name), format!("{}.txt", name)]
+        .iter()
+        .map(|filename| model.join(filename))
+        .find(|path| path.is_file())
+}
+
+fn is_binary(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "bin")
+}
+
+/// the lines of a text model file that aren't comments, with their 1-indexed
+/// line numbers
+fn read_lines(path: &Path) -> Result<Vec<(usize, String)>> {
+    let contents = fs::read_to_string(path).map_err(|e| CarvingError::io(path, e))?;
+    Ok(contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with('#'))
+        .map(|(i, line)| (i + 1, line.to_string()))
+        .collect())
+}
+
+/// parse whitespace separated values from a line of a text model file
+fn parse_values<T: std::str::FromStr>(path: &Path, line: usize, values: &[&str]) -> Result<Vec<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    values
+        .iter()
+        .map(|v| {
+            v.parse::<T>()
+                .map_err(|e| CarvingError::parse(path, line, format!("bad value `{}`: {}", v, e)))
+        })
+        .collect()
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i32(reader: &mut impl Read) -> io::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_f64s<const N: usize>(reader: &mut impl Read) -> io::Result<[f64; N]> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        *value = read_f64(reader)?;
+    }
+    Ok(values)
+}
+
+/// skip `count` bytes
+fn skip(reader: &mut impl Read, count: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(count), &mut io::sink())?;
+    if skipped < count {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| CarvingError::io(path, e))
+}
+
+/// the name COLMAP gives to the camera model with id `model`
+fn model_name(model: i32) -> &'static str {
+    match model {
+        0 => "SIMPLE_PINHOLE",
+        1 => "PINHOLE",
+        2 => "SIMPLE_RADIAL",
+        3 => "RADIAL",
+        4 => "OPENCV",
+        5 => "OPENCV_FISHEYE",
+        6 => "FULL_OPENCV",
+        7 => "FOV",
+        8 => "SIMPLE_RADIAL_FISHEYE",
+        9 => "RADIAL_FISHEYE",
+        10 => "THIN_PRISM_FISHEYE",
+        _ => "UNKNOWN",
+    }
+}
+
+/// the number of parameters of the camera model with id `model`
+fn model_parameters(model: i32) -> Option<usize> {
+    match model {
+        0 => Some(3),
+        1 | 2 | 8 => Some(4),
+        3 | 7 | 9 => Some(5),
+        4 | 5 => Some(8),
+        6 | 10 => Some(12),
+        _ => None,
+    }
+}
+
+/// read `cameras.txt` or `cameras.bin` into intrinsics by camera id
+fn read_intrinsics(path: &Path) -> Result<HashMap<u32, Intrinsics>> {
+    let mut cameras = HashMap::new();
+
+    if is_binary(path) {
+        let mut reader = open(path)?;
+        let mut read = || -> io::Result<()> {
+            let count = read_u64(&mut reader)?;
+            for _ in 0..count {
+                let id = read_u32(&mut reader)?;
+                let model = read_i32(&mut reader)?;
+                let _width = read_u64(&mut reader)?;
+                let _height = read_u64(&mut reader)?;
+                let parameters = model_parameters(model).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown camera model id {}", model),
+                    )
+                })?;
+                let params = (0..parameters)
+                    .map(|_| read_f64(&mut reader))
+                    .collect::<io::Result<Vec<f64>>>()?;
+                let intrinsics = Intrinsics::new(model_name(model), &params)
+                    .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))?;
+                cameras.insert(id, intrinsics);
+            }
+            Ok(())
+        };
+        read().map_err(|e| CarvingError::io(path, e))?;
+    } else {
+        // CAMERA_ID MODEL WIDTH HEIGHT PARAMS[]
+        for (line, text) in read_lines(path)? {
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() < 4 {
+                return Err(CarvingError::parse(
+                    path,
+                    line,
+                    "expected a camera id, model, width, height and parameters",
+                ));
+            }
+            let id = parse_values::<u32>(path, line, &fields[0..1])?[0];
+            let params = parse_values::<f64>(path, line, &fields[4..])?;
+            let intrinsics = Intrinsics::new(fields[1], &params)
+                .map_err(|message| CarvingError::parse(path, line, message))?;
+            cameras.insert(id, intrinsics);
+        }
+    }
+
+    Ok(cameras)
+}
+
+/// An entry of `images.txt` or `images.bin`
+struct Image {
+    name: String,
+    camera_id: u32,
+    quaternion: [f64; 4],
+    translation: [f64; 3],
+}
+
+/// read `images.txt` or `images.bin`
+fn read_images(path: &Path) -> Result<Vec<Image>> {
+    let mut images = vec![];
+
+    if is_binary(path) {
+        let mut reader = open(path)?;
+        let mut read = || -> io::Result<()> {
+            let count = read_u64(&mut reader)?;
+            for _ in 0..count {
+                let _id = read_u32(&mut reader)?;
+                let quaternion = read_f64s::<4>(&mut reader)?;
+                let translation = read_f64s::<3>(&mut reader)?;
+                let camera_id = read_u32(&mut reader)?;
+                let mut name = vec![];
+                reader.read_until(0, &mut name)?;
+                if name.pop() != Some(0) {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                // Each 2d point is an x and y coordinate and a 3d point id
+                let points = read_u64(&mut reader)?;
+                skip(&mut reader, points * 24)?;
+
+                images.push(Image {
+                    name: String::from_utf8_lossy(&name).into_owned(),
+                    camera_id,
+                    quaternion,
+                    translation,
+                });
+            }
+            Ok(())
+        };
+        read().map_err(|e| CarvingError::io(path, e))?;
+    } else {
+        // Each image takes two lines, the first holding
+        // "IMAGE_ID QW QX QY QZ TX TY TZ CAMERA_ID NAME" and the second its
+        // 2d points, which may be empty
+        let mut points_line = false;
+        for (line, text) in read_lines(path)? {
+            if points_line {
+                points_line = false;
+                continue;
+            }
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() != 10 {
+                return Err(CarvingError::parse(
+                    path,
+                    line,
+                    format!("expected 10 fields for an image, found {}", fields.len()),
+                ));
+            }
+            let pose = parse_values::<f64>(path, line, &fields[1..8])?;
+            images.push(Image {
+                name: fields[9].to_string(),
+                camera_id: parse_values::<u32>(path, line, &fields[8..9])?[0],
+                quaternion: [pose[0], pose[1], pose[2], pose[3]],
+                translation: [pose[4], pose[5], pose[6]],
+            });
+            points_line = true;
+        }
+    }
+
+    Ok(images)
+}
+
+/// read the registered images of the COLMAP model in `model` as
+/// `(image filename, camera)` pairs, sorted by filename
+pub(crate) fn read_cameras(model: &Path) -> Result<Vec<(String, CameraData)>> {
+    let missing = |name: &str| {
+        CarvingError::io(
+            model.join(format!("{}.bin", name)),
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no {}.bin or {}.txt in the model", name, name),
+            ),
+        )
+    };
+    let cameras_path = model_file(model, "cameras").ok_or_else(|| missing("cameras"))?;
+    let images_path = model_file(model, "images").ok_or_else(|| missing("images"))?;
+
+    let intrinsics = read_intrinsics(&cameras_path)?;
+    let mut cameras = read_images(&images_path)?
+        .into_iter()
+        .map(|image| {
+            let camera = intrinsics.get(&image.camera_id).ok_or_else(|| {
+                CarvingError::io(
+                    &images_path,
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} uses unknown camera {}", image.name, image.camera_id),
+                    ),
+                )
+            })?;
+            Ok((
+                image.name,
+                camera.camera(image.quaternion, image.translation),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    cameras.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(cameras)
+}
+
+/// read the positions of the points in `points3D.txt` or `points3D.bin`
+fn read_points(path: &Path) -> Result<Vec<glm::Vec3>> {
+    let mut points = vec![];
+
+    if is_binary(path) {
+        let mut reader = open(path)?;
+        let mut read = || -> io::Result<()> {
+            let count = read_u64(&mut reader)?;
+            for _ in 0..count {
+                let _id = read_u64(&mut reader)?;
+                let [x, y, z] = read_f64s::<3>(&mut reader)?;
+                let _color = [
+                    read_u8(&mut reader)?,
+                    read_u8(&mut reader)?,
+                    read_u8(&mut reader)?,
+                ];
+                let _error = read_f64(&mut reader)?;
+                // Each track element is an image id and a 2d point index
+                let track = read_u64(&mut reader)?;
+                skip(&mut reader, track * 8)?;
+
+                points.push(glm::vec3(x as f32, y as f32, z as f32));
+            }
+            Ok(())
+        };
+        read().map_err(|e| CarvingError::io(path, e))?;
+    } else {
+        // POINT3D_ID X Y Z R G B ERROR TRACK[]
+        for (line, text) in read_lines(path)? {
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() < 4 {
+                return Err(CarvingError::parse(
+                    path,
+                    line,
+                    "expected a point id and position",
+                ));
+            }
+            let position = parse_values::<f32>(path, line, &fields[1..4])?;
+            points.push(glm::make_vec3(&position));
+        }
+    }
+
+    Ok(points)
+}
+
+/// a bounding box around the sparse points of the COLMAP model in `model`, as
+/// `(front_top_left, back_bottom_right)`. The furthest 1% of the points along
+/// each axis are ignored as outliers, and the box is grown by 10% on every side.
+/// `None` if the model has no points.
+pub(crate) fn points_bounding_box(model: &Path) -> Result<Option<(glm::Vec3, glm::Vec3)>> {
+    let points = match model_file(model, "points3D") {
+        Some(path) => read_points(&path)?,
+        None => return Ok(None),
+    };
+    if points.is_empty() {
+        return Ok(None);
+    }
+
+    let mut min = glm::vec3(0.0, 0.0, 0.0);
+    let mut max = glm::vec3(0.0, 0.0, 0.0);
+    let trimmed = (points.len() as f32 * OUTLIER_FRACTION) as usize;
+    for axis in 0..3 {
+        let mut values: Vec<f32> = points.iter().map(|p| p[axis]).collect();
+        values.sort_by(|a, b| a.total_cmp(b));
+        min[axis] = values[trimmed];
+        max[axis] = values[values.len() - 1 - trimmed];
+    }
+    let margin = (max - min) * MARGIN;
+    let (min, max) = (min - margin, max + margin);
+
+    Ok(Some((
+        glm::vec3(min.x, max.y, max.z),
+        glm::vec3(max.x, min.y, min.z),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestCamera {
+        id: u32,
+        model_id: i32,
+        model: &'static str,
+        params: &'static [f64],
+    }
+
+    struct TestImage {
+        id: u32,
+        quaternion: [f64; 4],
+        translation: [f64; 3],
+        camera_id: u32,
+        name: &'static str,
+    }
+
+    /// the cameras of the test model: a plain pinhole and one with radial
+    /// distortion
+    const CAMERAS: [TestCamera; 2] = [
+        TestCamera {
+            id: 1,
+            model_id: 1,
+            model: "PINHOLE",
+            params: &[500.0, 510.0, 320.0, 240.0],
+        },
+        TestCamera {
+            id: 2,
+            model_id: 2,
+            model: "SIMPLE_RADIAL",
+            params: &[250.0, 160.0, 120.0, 0.1],
+        },
+    ];
+    /// the images of the test model, listed out of name order
+    const IMAGES: [TestImage; 2] = [
+        TestImage {
+            id: 1,
+            quaternion: [1.0, 0.0, 0.0, 0.0],
+            translation: [0.0, 0.0, 2.0],
+            camera_id: 1,
+            name: "b.png",
+        },
+        // a half turn about y, scaled to check that it's normalized
+        TestImage {
+            id: 2,
+            quaternion: [0.0, 0.0, 2.0, 0.0],
+            translation: [0.5, 0.0, 1.0],
+            camera_id: 2,
+            name: "a.png",
+        },
+    ];
+    /// the width and height of every camera, which aren't used
+    const SIZE: [u64; 2] = [640, 480];
+    const POINTS: [[f64; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 2.0, 3.0], [-1.0, 0.5, 1.0]];
+
+    /// an empty folder for a model
+    fn model_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!(
+            "space-carving-colmap-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn write_text_model(folder: &Path) {
+        let mut cameras = "# Camera list\n".to_string();
+        for camera in &CAMERAS {
+            let params: Vec<String> = camera.params.iter().map(f64::to_string).collect();
+            cameras += &format!(
+                "{} {} {} {} {}\n",
+                camera.id,
+                camera.model,
+                SIZE[0],
+                SIZE[1],
+                params.join(" ")
+            );
+        }
+        fs::write(folder.join("cameras.txt"), cameras).unwrap();
+
+        let mut images = "# Image list with two lines of data per image\n".to_string();
+        for image in &IMAGES {
+            let [qw, qx, qy, qz] = image.quaternion;
+            let [tx, ty, tz] = image.translation;
+            images += &format!(
+                "{} {} {} {} {} {} {} {} {} {}\n",
+                image.id, qw, qx, qy, qz, tx, ty, tz, image.camera_id, image.name
+            );
+            // the first image has a 2d point, the second none
+            images += if image.id == 1 { "10.5 20.5 1\n" } else { "\n" };
+        }
+        fs::write(folder.join("images.txt"), images).unwrap();
+
+        let mut points = String::new();
+        for (i, [x, y, z]) in POINTS.iter().enumerate() {
+            points += &format!("{} {} {} {} 255 128 0 0.5 1 0\n", i + 1, x, y, z);
+        }
+        fs::write(folder.join("points3D.txt"), points).unwrap();
+    }
+
+    fn write_binary_model(folder: &Path) {
+        let mut cameras = (CAMERAS.len() as u64).to_le_bytes().to_vec();
+        for camera in &CAMERAS {
+            cameras.extend(camera.id.to_le_bytes());
+            cameras.extend(camera.model_id.to_le_bytes());
+            cameras.extend(SIZE.iter().flat_map(|s| s.to_le_bytes()));
+            cameras.extend(camera.params.iter().flat_map(|p| p.to_le_bytes()));
+        }
+        fs::write(folder.join("cameras.bin"), cameras).unwrap();
+
+        let mut images = (IMAGES.len() as u64).to_le_bytes().to_vec();
+        for image in &IMAGES {
+            images.extend(image.id.to_le_bytes());
+            images.extend(image.quaternion.iter().flat_map(|q| q.to_le_bytes()));
+            images.extend(image.translation.iter().flat_map(|t| t.to_le_bytes()));
+            images.extend(image.camera_id.to_le_bytes());
+            images.extend(image.name.as_bytes());
+            images.push(0);
+            // one 2d point: x, y and a 3d point id
+            images.extend(1u64.to_le_bytes());
+            images.extend(10.5f64.to_le_bytes());
+            images.extend(20.5f64.to_le_bytes());
+            images.extend(1u64.to_le_bytes());
+        }
+        fs::write(folder.join("images.bin"), images).unwrap();
+
+        let mut points = (POINTS.len() as u64).to_le_bytes().to_vec();
+        for (i, position) in POINTS.iter().enumerate() {
+            points.extend((i as u64 + 1).to_le_bytes());
+            points.extend(position.iter().flat_map(|p| p.to_le_bytes()));
+            points.extend([255, 128, 0]);
+            points.extend(0.5f64.to_le_bytes());
+            // a track of one image id and 2d point index
+            points.extend(1u64.to_le_bytes());
+            points.extend(1u32.to_le_bytes());
+            points.extend(0u32.to_le_bytes());
+        }
+        fs::write(folder.join("points3D.bin"), points).unwrap();
+    }
+
+    fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+        assert!(glm::distance(&a, &b) < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    /// check the cameras and bounding box read from the test model
+    fn check_model(model: &Path) {
+        let cameras = read_cameras(model).unwrap();
+        let names: Vec<&str> = cameras.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a.png", "b.png"]);
+        let (a, b) = (&cameras[0].1, &cameras[1].1);
+
+        // The identity pose puts the camera at -t looking down +z
+        assert_close(b.center(), glm::vec3(0.0, 0.0, -2.0));
+        let pixel = b.project(&glm::vec3(0.2, -0.1, 0.0)).unwrap();
+        assert!(
+            (pixel - glm::vec2(370.0, 214.5)).norm() < 1e-3,
+            "{:?}",
+            pixel
+        );
+        assert!(b.distortion().is_none());
+
+        // Turned half way about y, the camera is at -R^T t
+        assert_close(a.center(), glm::vec3(0.5, 0.0, 1.0));
+        assert_close(a.forward(), glm::vec3(0.0, 0.0, -1.0));
+        assert_eq!(a.distortion(), Some(&Distortion::radial(0.1, 0.0)));
+
+        let (front_top_left, back_bottom_right) = points_bounding_box(model).unwrap().unwrap();
+        assert_close(front_top_left, glm::vec3(-1.2, 2.2, 3.3));
+        assert_close(back_bottom_right, glm::vec3(1.2, -0.2, -0.3));
+    }
+
+    #[test]
+    fn reads_text_models() {
+        let model = model_folder("text");
+        write_text_model(&model);
+        check_model(&model);
+        fs::remove_dir_all(model).unwrap();
+    }
+
+    #[test]
+    fn reads_binary_models() {
+        let model = model_folder("binary");
+        write_binary_model(&model);
+        check_model(&model);
+        fs::remove_dir_all(model).unwrap();
+    }
+
+    #[test]
+    fn prefers_binary_files() {
+        let model = model_folder("both");
+        write_binary_model(&model);
+        fs::write(model.join("cameras.txt"), "not a camera\n").unwrap();
+        fs::write(model.join("images.txt"), "not an image\n").unwrap();
+        check_model(&model);
+        fs::remove_dir_all(model).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_models() {
+        let model = model_folder("bad");
+        write_text_model(&model);
+        fs::write(model.join("cameras.txt"), "1 FOV 640 480 500 320 240 0.1\n").unwrap();
+        assert!(read_cameras(&model).is_err());
+
+        write_binary_model(&model);
+        let images = fs::read(model.join("images.bin")).unwrap();
+        fs::write(model.join("images.bin"), &images[..images.len() - 4]).unwrap();
+        assert!(read_cameras(&model).is_err());
+        fs::remove_dir_all(model).unwrap();
+    }
+}
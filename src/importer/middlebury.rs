@@ -0,0 +1,48 @@
+//! The Middlebury multi-view stereo layout: a `<prefix>_par.txt` file listing
+//! every image with its full projection matrix.
+use crate::error::{CarvingError, Result};
+use crate::view::CameraData;
+use std::fs;
+use std::path::Path;
+
+/// read the cameras listed in a Middlebury-style `_par.txt` file as
+/// `(image filename, camera)` pairs, in the order they're listed
+pub(crate) fn read_cameras(filename: &Path) -> Result<Vec<(String, CameraData)>> {
+    let metadata = fs::read_to_string(filename).map_err(|e| CarvingError::io(filename, e))?;
+
+    // The first line holds the number of images, every following line holds
+    // "imgname.png k11 ... k33 r11 ... r33 t1 t2 t3"
+    metadata
+        .lines()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let line_number = i + 1;
+            let mut fields = line.split_whitespace();
+            let name = fields.next().unwrap_or_default().to_string();
+            let values = fields
+                .map(|n| {
+                    n.parse::<f32>().map_err(|e| {
+                        CarvingError::parse(
+                            filename,
+                            line_number,
+                            format!("bad value `{}`: {}", n, e),
+                        )
+                    })
+                })
+                .collect::<Result<Vec<f32>>>()?;
+
+            if values.len() != 21 {
+                return Err(CarvingError::parse(
+                    filename,
+                    line_number,
+                    format!("expected 21 camera parameters, found {}", values.len()),
+                ));
+            }
+
+            let camera = CameraData::new(&values[0..9], &values[9..18], &values[18..21]);
+            Ok((name, camera))
+        })
+        .collect()
+}
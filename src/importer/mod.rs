@@ -0,0 +1,463 @@
+use crate::background::Background;
+use crate::brdf::{Light, Measure};
+use crate::error::{CarvingError, Result};
+use crate::view::{CameraData, View};
+use image::{DynamicImage, GenericImageView};
+use indicatif::ProgressIterator;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+mod colmap;
+mod middlebury;
+mod nerf;
+
+/// A camera as a dataset lists it
+#[derive(Debug, Clone)]
+enum ListedCamera {
+    Calibrated(CameraData),
+    /// a NeRF-style frame, whose camera may need the size of its image
+    Frame(nerf::FrameCamera),
+}
+
+/// How a dataset stores its cameras
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// a Middlebury-style `<prefix>_par.txt` file listing every image with its
+    /// projection matrix
+    #[default]
+    Middlebury,
+    /// a COLMAP sparse model with `cameras`, `images` and `points3D` files
+    Colmap,
+    /// a NeRF-style `transforms.json` file listing every image with its
+    /// camera-to-world matrix
+    Nerf,
+}
+
+/// The contents of a dataset description file (e.g. `templeRing.json`)
+#[derive(Serialize, Deserialize)]
+pub struct Config {
+    /// Folder containing the images, and the `_par.txt` metadata file for
+    /// Middlebury-style datasets
+    pub directory: String,
+    /// How the cameras are stored, `middlebury` (the default), `colmap` or
+    /// `nerf`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub format: Format,
+    /// Common prefix of the image and metadata filenames of a Middlebury-style
+    /// dataset
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub prefix: String,
+    /// Folder containing the sparse model of a COLMAP dataset, e.g.
+    /// `sparse/0`. Defaults to `directory`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The transforms file of a NeRF-style dataset, relative to `directory`.
+    /// Defaults to `transforms.json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<String>,
+    // Bounding box coords. If they're left out, the bounding box is taken
+    // from the sparse points of COLMAP datasets, or estimated from the views,
+    // see `bbox::estimate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bb_front_top_left: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bb_back_bottom_right: Option<[f32; 3]>,
+    /// If set, each image `<name>.png` has a foreground mask `<name><mask_suffix>.png`
+    /// next to it. White pixels are foreground, black pixels are background.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask_suffix: Option<String>,
+    /// What the background looks like. Used to tell background pixels apart
+    /// from the object, and to derive silhouettes if there are no mask files.
+    /// Defaults to exactly black.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub background: Background,
+    /// The consistency measure that suits this dataset best, if not variance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consistency: Option<Measure>,
+    /// The light illuminating the scene, for the `specular` measure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<Light>,
+}
+impl Config {
+    /// The bounding box corners as `(front_top_left, back_bottom_right)`, if
+    /// the config has both
+    pub fn bounding_box(&self) -> Option<(glm::Vec3, glm::Vec3)> {
+        Some((
+            glm::make_vec3(&self.bb_front_top_left?),
+            glm::make_vec3(&self.bb_back_bottom_right?),
+        ))
+    }
+    /// Replace the bounding box corners
+    pub fn set_bounding_box(&mut self, front_top_left: glm::Vec3, back_bottom_right: glm::Vec3) {
+        self.bb_front_top_left = Some(front_top_left.into());
+        self.bb_back_bottom_right = Some(back_bottom_right.into());
+    }
+    /// The folder holding the sparse model of a COLMAP dataset
+    fn model(&self) -> &Path {
+        Path::new(self.model.as_ref().unwrap_or(&self.directory))
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// read and deserialize a dataset description file
+pub fn load_config(filename: &str) -> Result<Config> {
+    let dataset = fs::read_to_string(filename).map_err(|e| CarvingError::io(filename, e))?;
+
+    serde_json::from_str(&dataset)
+        .map_err(|e| CarvingError::parse(filename, e.line(), e.to_string()))
+}
+
+/// serialize a dataset description and write it to `filename`
+pub fn save_config(config: &Config, filename: &str) -> Result<()> {
+    let mut dataset =
+        serde_json::to_string_pretty(config).map_err(|e| CarvingError::io(filename, e.into()))?;
+    dataset.push('\n');
+
+    fs::write(filename, dataset).map_err(|e| CarvingError::io(filename, e))
+}
+
+/// read the background plate for `img`, which must be the same size
+fn load_plate(filename: &Path, img: &DynamicImage) -> Result<DynamicImage> {
+    let plate = image::open(filename).map_err(|source| CarvingError::MissingImage {
+        path: filename.to_path_buf(),
+        source,
+    })?;
+
+    if plate.dimensions() != img.dimensions() {
+        return Err(CarvingError::ImageSizeMismatch {
+            path: filename.to_path_buf(),
+            expected: img.dimensions(),
+            found: plate.dimensions(),
+        });
+    }
+
+    Ok(plate)
+}
+
+/// read a foreground mask image for `img`, which must be the same size,
+/// treating bright pixels as foreground
+fn load_silhouette(filename: &Path, img: &DynamicImage) -> Result<Vec<Vec<bool>>> {
+    let mask = image::open(filename).map_err(|source| CarvingError::MissingImage {
+        path: filename.to_path_buf(),
+        source,
+    })?;
+
+    if mask.dimensions() != img.dimensions() {
+        return Err(CarvingError::ImageSizeMismatch {
+            path: filename.to_path_buf(),
+            expected: img.dimensions(),
+            found: mask.dimensions(),
+        });
+    }
+
+    Ok(mask
+        .to_luma8()
+        .rows()
+        .map(|row| row.map(|pix| pix.0[0] > 127).collect())
+        .collect())
+}
+
+/// One part of an `ImageSelection`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Span {
+    /// every `step`th image, starting from the first
+    All { step: usize },
+    /// every `step`th image from `first` to `last` (inclusive, 1-indexed)
+    Range {
+        first: usize,
+        last: usize,
+        step: usize,
+    },
+    /// the first `count` images
+    First { count: usize },
+}
+
+/// Which of a dataset's images to load. Images are numbered from 1 in the
+/// order the metadata file lists them, which for the Middlebury datasets is
+/// the number in their name. Parsed from a comma-separated list of
+///
+/// - `all`, every image
+/// - `7`, a single image
+/// - `1-16`, a range of images, including both ends
+///
+/// where `all` and ranges can be followed by `/n` to only take every `n`th
+/// image, e.g. `all/4` or `1-24/2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSelection {
+    spans: Vec<Span>,
+}
+impl ImageSelection {
+    /// every image
+    pub fn all() -> Self {
+        ImageSelection {
+            spans: vec![Span::All { step: 1 }],
+        }
+    }
+    /// the first `count` images, or every image if there are fewer
+    pub fn first(count: usize) -> Self {
+        ImageSelection {
+            spans: vec![Span::First { count }],
+        }
+    }
+    /// the 0-indexed positions of the selected images out of `count`, in
+    /// order and without duplicates
+    pub fn indices(&self, count: usize) -> Result<Vec<usize>> {
+        let mut indices = vec![];
+        for span in &self.spans {
+            match *span {
+                Span::All { step } => indices.extend((0..count).step_by(step)),
+                Span::Range { first, last, step } => {
+                    if last > count {
+                        return Err(CarvingError::InvalidSelection(format!(
+                            "image {} was selected but there are only {} images",
+                            last, count
+                        )));
+                    }
+                    indices.extend((first - 1..last).step_by(step));
+                }
+                Span::First { count: first } => indices.extend(0..first.min(count)),
+            }
+        }
+        indices.sort_unstable();
+        indices.dedup();
+
+        if indices.is_empty() {
+            return Err(CarvingError::InvalidSelection(
+                "no images were selected".to_string(),
+            ));
+        }
+        Ok(indices)
+    }
+}
+impl Default for ImageSelection {
+    fn default() -> Self {
+        ImageSelection::all()
+    }
+}
+impl FromStr for ImageSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let number = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("`{}` isn't a positive image number", n.trim()))
+        };
+
+        let spans = s
+            .split(',')
+            .map(|part| {
+                let (range, step) = match part.split_once('/') {
+                    Some((range, step)) => (range.trim(), number(step)?),
+                    None => (part.trim(), 1),
+                };
+                if range == "all" {
+                    return Ok(Span::All { step });
+                }
+                let (first, last) = match range.split_once('-') {
+                    Some((first, last)) => (number(first)?, number(last)?),
+                    None if step == 1 => (number(range)?, number(range)?),
+                    None => {
+                        return Err(format!(
+                            "`{}` is a single image and can't have a step",
+                            part
+                        ))
+                    }
+                };
+                if first > last {
+                    return Err(format!("`{}` ends before it starts", range));
+                }
+                Ok(Span::Range { first, last, step })
+            })
+            .collect::<std::result::Result<Vec<Span>, String>>()?;
+
+        Ok(ImageSelection { spans })
+    }
+}
+impl fmt::Display for ImageSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, span) in self.spans.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            match *span {
+                Span::All { .. } => write!(f, "all")?,
+                Span::Range { first, last, step } if first == last && step == 1 => {
+                    write!(f, "{}", first)?
+                }
+                Span::Range { first, last, .. } => write!(f, "{}-{}", first, last)?,
+                Span::First { count } => write!(f, "1-{}", count)?,
+            }
+            if let Span::All { step } | Span::Range { step, .. } = *span {
+                if step > 1 {
+                    write!(f, "/{}", step)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// a bounding box derived from the dataset itself rather than the config, as
+/// `(front_top_left, back_bottom_right)`. For COLMAP datasets, this is the box
+/// around most of the sparse points, grown by a small margin. `None` for other
+/// datasets, or if there are no points.
+pub fn points_bounding_box(config: &Config) -> Result<Option<(glm::Vec3, glm::Vec3)>> {
+    match config.format {
+        Format::Middlebury | Format::Nerf => Ok(None),
+        Format::Colmap => colmap::points_bounding_box(config.model()),
+    }
+}
+
+/// the alpha channel of an image as a silhouette, treating mostly opaque
+/// pixels as foreground. `None` if the image has no alpha channel or is opaque
+/// everywhere.
+fn alpha_silhouette(img: &DynamicImage) -> Option<Vec<Vec<bool>>> {
+    if !img.color().has_alpha() {
+        return None;
+    }
+    let img = img.to_rgba8();
+    if img.pixels().all(|pix| pix.0[3] == 255) {
+        return None;
+    }
+
+    Some(
+        img.rows()
+            .map(|row| row.map(|pix| pix.0[3] > 127).collect())
+            .collect(),
+    )
+}
+
+/// load the selected views of a dataset. Middlebury-style datasets list every
+/// image in `directory` along with its calibration in
+/// `<directory>/<prefix>_par.txt`, COLMAP datasets list the images their sparse
+/// model registered, sorted by name, and NeRF-style datasets list their frames
+/// in a transforms file. Views get a silhouette from its mask file if the
+/// config has a `mask_suffix`, from the image's alpha channel if it has one,
+/// or derived from the background model otherwise.
+pub fn load_views(config: &Config, selection: &ImageSelection) -> Result<Vec<View>> {
+    let location = Path::new(&config.directory);
+
+    let calibrated = |cameras: Vec<(String, CameraData)>| {
+        cameras
+            .into_iter()
+            .map(|(filename, camera)| (filename, ListedCamera::Calibrated(camera)))
+            .collect::<Vec<_>>()
+    };
+    let cameras = match config.format {
+        Format::Middlebury => calibrated(middlebury::read_cameras(
+            &location.join(format!("{}_par.txt", config.prefix)),
+        )?),
+        Format::Colmap => calibrated(colmap::read_cameras(config.model())?),
+        Format::Nerf => nerf::read_cameras(
+            location,
+            config.transforms.as_deref().unwrap_or("transforms.json"),
+        )?
+        .into_iter()
+        .map(|(filename, camera)| (filename, ListedCamera::Frame(camera)))
+        .collect(),
+    };
+    let selected: Vec<(String, ListedCamera)> = selection
+        .indices(cameras.len())?
+        .into_iter()
+        .map(|i| cameras[i].clone())
+        .collect();
+
+    selected
+        .into_iter()
+        .progress()
+        .map(|(filename, camera)| {
+            let path = location.join(&filename);
+            let name = Path::new(&filename).file_stem().map_or_else(
+                || filename.clone(),
+                |stem| stem.to_string_lossy().into_owned(),
+            );
+            // masks and plates are stored next to their image
+            let sibling = |suffix: &str| path.with_file_name(format!("{}{}.png", name, suffix));
+
+            let img = image::open(&path).map_err(|source| CarvingError::MissingImage {
+                path: path.clone(),
+                source,
+            })?;
+            let camera = match camera {
+                ListedCamera::Calibrated(camera) => camera,
+                ListedCamera::Frame(frame) => frame.camera(img.width(), img.height())?,
+            };
+
+            let plate = match config.background.plate_suffix() {
+                Some(suffix) => Some(load_plate(&sibling(suffix), &img)?),
+                None => None,
+            };
+
+            let silhouette = match &config.mask_suffix {
+                Some(suffix) => load_silhouette(&sibling(suffix), &img)?,
+                None => alpha_silhouette(&img)
+                    .unwrap_or_else(|| config.background.silhouette(&img, plate.as_ref())),
+            };
+
+            let mut view = View::new(camera, img);
+            view.name = name;
+            view.silhouette = Some(silhouette);
+            view.background_color = config.background.color();
+            view.plate = plate.map(Box::new);
+            Ok(view)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(selection: &str, count: usize) -> Result<Vec<usize>> {
+        selection.parse::<ImageSelection>().unwrap().indices(count)
+    }
+
+    #[test]
+    fn selects_every_nth_image() {
+        assert_eq!(indices("all", 4).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(indices("all/4", 10).unwrap(), vec![0, 4, 8]);
+        assert_eq!(indices("3-7/2", 10).unwrap(), vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn merges_spans_in_order() {
+        assert_eq!(indices("9, 1-3, 2", 10).unwrap(), vec![0, 1, 2, 8]);
+        assert_eq!(ImageSelection::first(3).indices(10).unwrap(), vec![0, 1, 2]);
+        assert_eq!(ImageSelection::first(30).indices(2).unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn rejects_invalid_selections() {
+        assert!("7-3".parse::<ImageSelection>().is_err());
+        assert!("0".parse::<ImageSelection>().is_err());
+        assert!("5/2".parse::<ImageSelection>().is_err());
+        assert!("1-x".parse::<ImageSelection>().is_err());
+        assert!(matches!(
+            indices("1-12", 10),
+            Err(CarvingError::InvalidSelection(_))
+        ));
+        assert!(matches!(
+            indices("11", 10),
+            Err(CarvingError::InvalidSelection(_))
+        ));
+    }
+
+    #[test]
+    fn displays_like_it_parses() {
+        for selection in ["all", "all/4", "3-7/2", "7", "1-4,9"] {
+            let parsed: ImageSelection = selection.parse().unwrap();
+            assert_eq!(parsed.to_string(), selection);
+        }
+    }
+}
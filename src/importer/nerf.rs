@@ -0,0 +1,298 @@
+//! NeRF-style `transforms.json` files, as used by the original NeRF synthetic
+//! scenes, instant-ngp and nerfstudio. Every frame gives an image and its 4x4
+//! camera-to-world matrix in OpenGL convention, where the camera looks down
+//! its -z axis with y up. The intrinsics are either a horizontal field of view
+//! (`camera_angle_x`) or focal lengths and a principal point in pixels, for
+//! all frames at once or for each frame.
+use crate::error::{CarvingError, Result};
+use crate::view::{CameraData, Distortion};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Intrinsics that can be given for all frames or for each frame
+#[derive(Debug, Clone, Default, Deserialize)]
+struct Intrinsics {
+    camera_angle_x: Option<f32>,
+    camera_angle_y: Option<f32>,
+    fl_x: Option<f32>,
+    fl_y: Option<f32>,
+    cx: Option<f32>,
+    cy: Option<f32>,
+    w: Option<f32>,
+    h: Option<f32>,
+    k1: Option<f32>,
+    k2: Option<f32>,
+    k3: Option<f32>,
+    k4: Option<f32>,
+    p1: Option<f32>,
+    p2: Option<f32>,
+    /// nerfstudio's name for the lens model, `OPENCV_FISHEYE` for a fisheye
+    camera_model: Option<String>,
+    /// instant-ngp's fisheye flag
+    is_fisheye: Option<bool>,
+}
+impl Intrinsics {
+    /// these intrinsics, with any that are missing taken from `defaults`
+    fn or(self, defaults: &Intrinsics) -> Intrinsics {
+        Intrinsics {
+            camera_angle_x: self.camera_angle_x.or(defaults.camera_angle_x),
+            camera_angle_y: self.camera_angle_y.or(defaults.camera_angle_y),
+            fl_x: self.fl_x.or(defaults.fl_x),
+            fl_y: self.fl_y.or(defaults.fl_y),
+            cx: self.cx.or(defaults.cx),
+            cy: self.cy.or(defaults.cy),
+            w: self.w.or(defaults.w),
+            h: self.h.or(defaults.h),
+            k1: self.k1.or(defaults.k1),
+            k2: self.k2.or(defaults.k2),
+            k3: self.k3.or(defaults.k3),
+            k4: self.k4.or(defaults.k4),
+            p1: self.p1.or(defaults.p1),
+            p2: self.p2.or(defaults.p2),
+            camera_model: self.camera_model.or_else(|| defaults.camera_model.clone()),
+            is_fisheye: self.is_fisheye.or(defaults.is_fisheye),
+        }
+    }
+    /// the row-major intrinsic matrix for an image of the given size, or
+    /// `None` if there's no focal length or field of view. Focal lengths and
+    /// principal points are given for an image of size `w` by `h`, so they're
+    /// scaled if the image has been resized since. A missing vertical focal
+    /// length is taken to be the same as the horizontal one, and a missing
+    /// principal point to be the middle of the image.
+    fn matrix(&self, width: u32, height: u32) -> Option<[f32; 9]> {
+        let (width, height) = (width as f32, height as f32);
+        let scale_x = self.w.map_or(1.0, |w| width / w);
+        let scale_y = self.h.map_or(1.0, |h| height / h);
+        let focal = |size: f32, angle: f32| 0.5 * size / (0.5 * angle).tan();
+
+        let fl_x = match (self.fl_x, self.camera_angle_x) {
+            (Some(fl_x), _) => fl_x * scale_x,
+            (None, Some(angle)) => focal(width, angle),
+            (None, None) => return None,
+        };
+        let fl_y = match (self.fl_y, self.camera_angle_y) {
+            (Some(fl_y), _) => fl_y * scale_y,
+            (None, Some(angle)) => focal(height, angle),
+            (None, None) => fl_x,
+        };
+        let cx = self.cx.map_or(width / 2.0, |cx| cx * scale_x);
+        let cy = self.cy.map_or(height / 2.0, |cy| cy * scale_y);
+
+        Some([fl_x, 0.0, cx, 0.0, fl_y, cy, 0.0, 0.0, 1.0])
+    }
+    /// the lens distortion, if there is any. Fisheyes use `k1` to `k4`, other
+    /// lenses the Brown-Conrady `k1` to `k3`, `p1` and `p2`.
+    fn distortion(&self) -> Option<Distortion> {
+        let k = |k: Option<f32>| k.unwrap_or(0.0);
+        if self.is_fisheye == Some(true) || self.camera_model.as_deref() == Some("OPENCV_FISHEYE") {
+            return Some(Distortion::Fisheye {
+                k1: k(self.k1),
+                k2: k(self.k2),
+                k3: k(self.k3),
+                k4: k(self.k4),
+            });
+        }
+
+        let distortion = Distortion::BrownConrady {
+            k1: k(self.k1),
+            k2: k(self.k2),
+            k3: k(self.k3),
+            p1: k(self.p1),
+            p2: k(self.p2),
+        };
+        (!distortion.is_identity()).then_some(distortion)
+    }
+}
+
+#[derive(Deserialize)]
+struct Frame {
+    file_path: String,
+    transform_matrix: [[f32; 4]; 4],
+    #[serde(flatten)]
+    intrinsics: Intrinsics,
+}
+
+#[derive(Deserialize)]
+struct Transforms {
+    frames: Vec<Frame>,
+    #[serde(flatten)]
+    intrinsics: Intrinsics,
+}
+
+/// the camera for a camera-to-world matrix in OpenGL convention. Flipping the
+/// y and z axes turns it into the OpenCV convention of `CameraData`, where the
+/// camera looks down +z with y down, and inverting it gives `[R t]`.
+fn camera(k: &[f32; 9], camera_to_world: &[[f32; 4]; 4]) -> CameraData {
+    let m = camera_to_world;
+    // The rows of R are the columns of the camera-to-world rotation, with
+    // the y and z columns negated
+    let r = [
+        m[0][0], m[1][0], m[2][0], -m[0][1], -m[1][1], -m[2][1], -m[0][2], -m[1][2], -m[2][2],
+    ];
+    let position = [m[0][3], m[1][3], m[2][3]];
+    let mut t = [0.0; 3];
+    for (i, t) in t.iter_mut().enumerate() {
+        *t = -(0..3).map(|j| r[3 * i + j] * position[j]).sum::<f32>();
+    }
+
+    CameraData::new(k, &r, &t)
+}
+
+/// The camera of a frame. When the focal length is given as a field of view,
+/// the camera can only be worked out once the size of its image is known, so
+/// it's only done for the frames that are loaded.
+#[derive(Debug, Clone)]
+pub(crate) struct FrameCamera {
+    intrinsics: Intrinsics,
+    camera_to_world: [[f32; 4]; 4],
+    /// the transforms file and the frame's `file_path` in it, for errors
+    transforms: PathBuf,
+    file_path: String,
+}
+impl FrameCamera {
+    /// the camera for the frame's image, which is `width` by `height`
+    pub(crate) fn camera(&self, width: u32, height: u32) -> Result<CameraData> {
+        let k = self.intrinsics.matrix(width, height).ok_or_else(|| {
+            CarvingError::io(
+                &self.transforms,
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} has no focal length, expected `fl_x` or `camera_angle_x`",
+                        self.file_path
+                    ),
+                ),
+            )
+        })?;
+
+        let camera = camera(&k, &self.camera_to_world);
+        Ok(match self.intrinsics.distortion() {
+            Some(distortion) => camera.with_distortion(distortion),
+            None => camera,
+        })
+    }
+}
+
+/// read the frames of the transforms file at `directory/transforms` as
+/// `(image filename, camera)` pairs, in the order they're listed. Filenames
+/// are relative to `directory`, and get a `.png` extension if they have none,
+/// like the NeRF synthetic scenes expect.
+pub(crate) fn read_cameras(
+    directory: &Path,
+    transforms: &str,
+) -> Result<Vec<(String, FrameCamera)>> {
+    let path = directory.join(transforms);
+    let contents = fs::read_to_string(&path).map_err(|e| CarvingError::io(&path, e))?;
+    let parsed: Transforms = serde_json::from_str(&contents)
+        .map_err(|e| CarvingError::parse(&path, e.line(), e.to_string()))?;
+
+    let base = Path::new(transforms)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let defaults = parsed.intrinsics;
+    Ok(parsed
+        .frames
+        .into_iter()
+        .map(|frame| {
+            let mut filename: PathBuf = base.join(&frame.file_path);
+            if filename.extension().is_none() {
+                filename.set_extension("png");
+            }
+            let camera = FrameCamera {
+                intrinsics: frame.intrinsics.or(&defaults),
+                camera_to_world: frame.transform_matrix,
+                transforms: path.clone(),
+                file_path: frame.file_path,
+            };
+            (filename.to_string_lossy().into_owned(), camera)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm as glm;
+
+    /// write `contents` as the transforms file of a fresh directory
+    fn transforms(name: &str, contents: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "space-carving-nerf-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("transforms.json"), contents).unwrap();
+        directory
+    }
+
+    /// a camera 4 units up the z axis looking back at the origin, in OpenGL
+    /// convention
+    const LOOKING_DOWN_Z: &str = "[[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 4], [0, 0, 0, 1]]";
+
+    #[test]
+    fn reads_frames_with_shared_and_own_intrinsics() {
+        // A field of view of 2 atan(1/2) gives a focal length of the image width
+        let directory = transforms(
+            "intrinsics",
+            &format!(
+                r#"{{
+                    "camera_angle_x": {},
+                    "frames": [
+                        {{ "file_path": "./train/r_0", "transform_matrix": {m} }},
+                        {{ "file_path": "r_1.jpg", "fl_x": 400, "w": 400, "h": 400,
+                           "camera_model": "OPENCV_FISHEYE", "transform_matrix": {m} }}
+                    ]
+                }}"#,
+                2.0 * 0.5f32.atan(),
+                m = LOOKING_DOWN_Z
+            ),
+        );
+        let cameras = read_cameras(&directory, "transforms.json").unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let filenames: Vec<&str> = cameras.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(filenames, ["./train/r_0.png", "r_1.jpg"]);
+
+        // Right in the scene is right in the image, and up is up
+        let camera = cameras[0].1.camera(200, 100).unwrap();
+        assert!(glm::distance(&camera.center(), &glm::vec3(0.0, 0.0, 4.0)) < 1e-5);
+        assert!(camera.distortion().is_none());
+        let right = camera.project(&glm::vec3(0.1, 0.0, 0.0)).unwrap();
+        assert!(glm::distance(&right, &glm::vec2(105.0, 50.0)) < 1e-3);
+        let up = camera.project(&glm::vec3(0.0, 0.1, 0.0)).unwrap();
+        assert!(glm::distance(&up, &glm::vec2(100.0, 45.0)) < 1e-3);
+
+        // The focal length was given for a 400 pixel wide image. With no
+        // rotation, `K*[R t]` holds the focal lengths, with y flipped
+        let camera = cameras[1].1.camera(800, 800).unwrap();
+        assert!(matches!(
+            camera.distortion(),
+            Some(Distortion::Fisheye { .. })
+        ));
+        let k = camera.projection_matrix();
+        assert_eq!((k[(0, 0)], k[(1, 1)]), (800.0, -800.0));
+    }
+
+    #[test]
+    fn rejects_frames_without_a_focal_length() {
+        let directory = transforms(
+            "no-focal",
+            &format!(
+                r#"{{ "frames": [{{ "file_path": "r_0", "transform_matrix": {} }}] }}"#,
+                LOOKING_DOWN_Z
+            ),
+        );
+        let cameras = read_cameras(&directory, "transforms.json").unwrap();
+        assert!(cameras[0].1.camera(100, 100).is_err());
+
+        fs::write(directory.join("transforms.json"), "{ \"frames\": [").unwrap();
+        assert!(matches!(
+            read_cameras(&directory, "transforms.json"),
+            Err(CarvingError::MetadataParse { .. })
+        ));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
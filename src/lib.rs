@@ -0,0 +1,56 @@
+//! A library for reconstructing a 3d volume from a set of calibrated images
+//! using the space carving method from Seitz and Kutulakos's "A Theory of
+//! Shape by Space Carving".
+//!
+//! The typical pipeline is:
+//!
+//! 1. load a dataset's [`importer::Config`] and its [`view::View`]s with
+//!    [`importer::load_views`], from a Middlebury-style `_par.txt` file, a
+//!    COLMAP sparse model or a NeRF-style `transforms.json`
+//! 2. create a [`volume::Volume`] covering the dataset's bounding box, or one
+//!    estimated from the views with [`bbox::estimate`]
+//! 3. carve the volume with [`carve::carve`] (or one of the other carving
+//!    [`carve::Mode`]s) and a [`brdf::ConsistencyCheck`], or with
+//!    [`carve::carve_checkpointed`] to save [`checkpoint`]s along the way
+//! 4. write the result out with one of the functions in [`exporter`]
+//!
+//! ```no_run
+//! use space_carving::brdf::Variance;
+//! use space_carving::importer::ImageSelection;
+//! use space_carving::exporter::{self, Encoding};
+//! use space_carving::{bbox, carve, importer, volume::Volume};
+//!
+//! # fn main() -> space_carving::error::Result<()> {
+//! let config = importer::load_config("templeRing.json")?;
+//! let mut views = importer::load_views(&config, &ImageSelection::first(16))?;
+//! let (front_top_left, back_bottom_right) = match config.bounding_box() {
+//!     Some(bounding_box) => bounding_box,
+//!     None => bbox::estimate(&views)?,
+//! };
+//! let mut volume = Volume::new(0.002, front_top_left, back_bottom_right)?;
+//!
+//! carve::carve(&mut volume, &mut views, &Variance { threshold: 0.4 })?;
+//! exporter::write_ply(&volume, "carved.ply", Encoding::Binary)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every fallible function returns an [`error::CarvingError`] describing what
+//! went wrong rather than panicking.
+
+pub mod background;
+pub mod bbox;
+pub mod brdf;
+pub mod carve;
+pub mod checkpoint;
+pub mod error;
+pub mod exporter;
+pub mod gvc;
+pub mod importer;
+pub mod mesh;
+pub mod misc;
+pub mod octree;
+pub mod probabilistic;
+pub mod view;
+pub mod visual_hull;
+pub mod volume;
@@ -1,79 +1,397 @@
-mod brdf;
-mod carve;
-mod exporter;
-mod importer;
-mod view;
-mod volume;
-
-use crate::volume::Volume;
-use clap::Parser;
-use nalgebra_glm as glm;
-use serde::{Deserialize, Serialize};
-use std::fs;
-
-/// Simple program to greet a person
+use clap::{ErrorKind, IntoApp, Parser};
+use space_carving::brdf::{ConsistencyCheck, Measure, Robust};
+use space_carving::carve::Mode;
+use space_carving::checkpoint::{self, Checkpoints, Progress};
+use space_carving::error::Result;
+use space_carving::importer::ImageSelection;
+use space_carving::octree::{self, Octree};
+use space_carving::volume::Volume;
+use space_carving::{bbox, carve, exporter, gvc, importer, mesh, probabilistic, visual_hull};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+/// Reconstruct a volume from a multi-view dataset via space carving
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     /// Folder with a dataset
-    #[clap(short, long)]
-    dataset: String,
+    #[clap(short, long, required_unless_present = "convert")]
+    dataset: Option<String>,
+
+    /// Which images to load, numbered from 1 in the order the dataset lists
+    /// them: `all`, or a comma-separated list of numbers and ranges like
+    /// `1-16`. `all` and ranges take every nth image with a `/n` suffix, e.g.
+    /// `all/4`
+    #[clap(short, long, default_value_t = ImageSelection::all(), conflicts_with = "num-images")]
+    images: ImageSelection,
 
-    /// number of images to load
+    /// Load only the first N images, short for `--images 1-N`
     #[clap(short, long)]
-    num_images: usize,
+    num_images: Option<usize>,
 
-    /// File to write .ply to
+    /// File to write the result to, as .ply, .obj, .stl, .glb, .vox or .nrrd
+    /// depending on its extension or `--format`
     #[clap(short, long, default_value = "carved.ply")]
     output: String,
 
+    /// How to mesh the result: cubes (a cube per surface voxel), faces (only
+    /// the exposed voxel faces, sharing vertices), greedy (exposed faces
+    /// merged into larger rectangles), surface-nets (a smooth triangle mesh
+    /// with vertex normals and colors) or points (a point cloud of the
+    /// surface voxels with estimated normals). greedy meshes aren't
+    /// watertight. Defaults to faces for stl output and cubes otherwise. Not
+    /// used for vox and nrrd output
+    #[clap(long)]
+    mesh: Option<mesh::Method>,
+
+    /// The format to write the output in: ply, obj, stl or glb for a mesh,
+    /// or vox or nrrd for the voxels themselves. Defaults to the one the
+    /// output's extension asks for, or ply
+    #[clap(long)]
+    format: Option<exporter::Format>,
+
+    /// How to store .ply output: ascii or binary (little endian)
+    #[clap(long, default_value_t = exporter::Encoding::Ascii)]
+    ply_encoding: exporter::Encoding,
+
+    /// File to write the occupancy probabilities of the probabilistic mode to,
+    /// as a .nrrd volume. Defaults to the output file with a .nrrd extension
+    #[clap(long)]
+    occupancy: Option<String>,
+
     /// The size of a voxel
     #[clap(short, long, default_value_t = 0.001)]
     voxel_size: f32,
 
-    /// The threshold of the carving algorithm
-    /// The lower the value, the more pixels will be carved
-    #[clap(short, long, default_value_t = 0.3)]
-    threshold: f32,
-}
+    /// The threshold of the consistency measure. Defaults to one suited to
+    /// the measure, e.g. 0.4 for variance
+    #[clap(short, long)]
+    threshold: Option<f32>,
 
-#[derive(Serialize, Deserialize)]
-struct Config {
-    directory: String,
-    prefix: String,
-    // Bounding box coords
-    bb_front_top_left: [f32; 3],
-    bb_back_bottom_right: [f32; 3],
-}
+    /// With the probabilistic mode, the expected color noise between views
+    #[clap(long, default_value_t = probabilistic::DEFAULT_NOISE)]
+    noise: f32,
 
-fn main() {
-    let args = Args::parse();
+    /// With the probabilistic mode, the log-odds of being occupied that a
+    /// voxel has to drop below to be carved. The further below 0, the more
+    /// checks have to speak against a voxel
+    #[clap(long, default_value_t = probabilistic::DEFAULT_CARVE_LOG_ODDS, allow_hyphen_values = true)]
+    carve_log_odds: f32,
+
+    /// The consistency measure to use: variance, delta-e, ncc, histogram, mad
+    /// or specular. Overrides the dataset's `consistency` field, defaults to variance
+    #[clap(short, long)]
+    consistency: Option<Measure>,
+
+    /// Accept voxels that enough views agree on, ignoring outlier views, and
+    /// report which views were rejected
+    #[clap(long)]
+    robust: bool,
+
+    /// With --robust, the minimum number of views that have to agree
+    #[clap(long, default_value_t = 3)]
+    min_inliers: usize,
+
+    /// With --robust, the minimum fraction of the views that have to agree
+    #[clap(long, default_value_t = 0.75)]
+    inlier_ratio: f32,
+
+    /// The carving algorithm to use: space-carving, gvc, visual-hull or
+    /// probabilistic
+    #[clap(short, long, default_value_t = Mode::SpaceCarving)]
+    mode: Mode,
+
+    /// Carve away everything outside the visual hull before running the
+    /// selected mode. Silhouettes come from the dataset's masks, the images'
+    /// alpha channels or the background model.
+    #[clap(long)]
+    hull_first: bool,
 
-    let dataset = fs::read_to_string(args.dataset).expect("Couldn't read dataset file");
+    /// Number of threads to carve with. 0 uses one thread per CPU core. The
+    /// result doesn't depend on the number of threads.
+    #[clap(long, default_value_t = 0)]
+    threads: usize,
 
-    // deserialize file to a config struct
-    let config: Config = serde_json::from_str(&dataset).unwrap();
+    /// Number of octree levels to carve from coarse to fine with
+    /// space-carving. 1 carves a dense volume at the full resolution. Only
+    /// space-carving can carve an octree, so other modes need 1.
+    #[clap(long, default_value_t = 1, conflicts_with = "hull-first")]
+    levels: u32,
+
+    /// Estimate the bounding box from the views, write it into the dataset
+    /// file and exit. Datasets without a bounding box get one estimated on
+    /// every run otherwise.
+    #[clap(long)]
+    estimate_bbox: bool,
+
+    /// Save space-carving checkpoints to this file, so that a carve can be
+    /// picked up again with --resume. No checkpoints are saved unless this or
+    /// --checkpoint-interval is given. Octrees carved with --levels can't be
+    /// saved
+    #[clap(long, conflicts_with = "levels")]
+    checkpoint: Option<String>,
+
+    /// Save space-carving checkpoints at most this many seconds apart, 300 by
+    /// default, and once carving is done. Checkpoints go to the output file
+    /// with a .volume extension unless --checkpoint is given
+    #[clap(long, conflicts_with = "levels")]
+    checkpoint_interval: Option<u64>,
+
+    /// Start from a volume saved with --checkpoint instead of an empty one.
+    /// space-carving picks up at the sweep it stopped at and carves exactly
+    /// what it would have without the interruption. The voxel size and
+    /// bounding box have to be the same as when it was saved
+    #[clap(long, conflicts_with = "levels")]
+    resume: Option<String>,
+
+    /// Export a volume saved with --checkpoint to --output without carving,
+    /// e.g. to another format or with another --mesh. No dataset is needed
+    #[clap(long, conflicts_with_all = &["resume", "estimate-bbox"])]
+    convert: Option<String>,
+}
+
+fn run(args: Args) -> Result<()> {
+    if let Some(saved) = &args.convert {
+        let (volume, _) = checkpoint::load(saved)?;
+        return export(&args, &volume);
+    }
+
+    let dataset = args.dataset.as_deref().unwrap_or_default();
+    let mut config = importer::load_config(dataset)?;
 
     println!("Loading views");
-    let mut views = importer::load_views(&config.directory, &config.prefix, args.num_images);
+    let selection = match args.num_images {
+        Some(count) => ImageSelection::first(count),
+        None => args.images.clone(),
+    };
+    let mut views = importer::load_views(&config, &selection)?;
     println!("Views loaded");
 
-    let bb_front_top_left = glm::vec3(
-        config.bb_front_top_left[0],
-        config.bb_front_top_left[1],
-        config.bb_front_top_left[2],
-    );
-    let bb_back_bottom_right = glm::vec3(
-        config.bb_back_bottom_right[0],
-        config.bb_back_bottom_right[1],
-        config.bb_back_bottom_right[2],
-    );
+    let bounding_box = match config.bounding_box() {
+        Some(bounding_box) => Some(bounding_box),
+        None => importer::points_bounding_box(&config)?,
+    };
+    let (bb_front_top_left, bb_back_bottom_right) = match bounding_box {
+        Some(bounding_box) if !args.estimate_bbox => bounding_box,
+        _ => {
+            println!("Estimating bounding box");
+            let (front_top_left, back_bottom_right) = bbox::estimate(&views)?;
+            println!(
+                "Estimated bounding box: {:?} to {:?}",
+                <[f32; 3]>::from(front_top_left),
+                <[f32; 3]>::from(back_bottom_right)
+            );
+            (front_top_left, back_bottom_right)
+        }
+    };
+
+    if args.estimate_bbox {
+        config.set_bounding_box(bb_front_top_left, bb_back_bottom_right);
+        return importer::save_config(&config, dataset);
+    }
 
-    let mut volume = Volume::new(args.voxel_size, bb_front_top_left, bb_back_bottom_right);
+    let measure = args
+        .consistency
+        .or(config.consistency)
+        .unwrap_or(Measure::Variance);
+    let threshold = args
+        .threshold
+        .unwrap_or_else(|| measure.default_threshold());
+    let robust = args.robust.then(|| {
+        Robust::new(
+            measure.build(threshold, config.light),
+            args.min_inliers,
+            args.inlier_ratio,
+        )
+    });
+    let plain = measure.build(threshold, config.light);
+    let check: &dyn ConsistencyCheck = match &robust {
+        Some(robust) => robust,
+        None => &*plain,
+    };
 
     // perform the carving
-    carve::carve(&mut volume, &mut views, args.threshold);
+    let volume = if args.levels > 1 {
+        let mut tree = Octree::new(args.voxel_size, bb_front_top_left, bb_back_bottom_right)?;
+        octree::carve(&mut tree, &mut views, check, args.levels)?;
+        tree.to_volume()?
+    } else {
+        let (mut volume, progress) = match &args.resume {
+            Some(saved) => {
+                let (volume, progress) = checkpoint::load(saved)?;
+                checkpoint::check_matches(
+                    &volume,
+                    args.voxel_size,
+                    bb_front_top_left,
+                    bb_back_bottom_right,
+                )?;
+                println!(
+                    "Resuming a {}x{}x{} volume after {} sweeps",
+                    volume.width, volume.height, volume.depth, progress.sweeps
+                );
+                (volume, progress)
+            }
+            None => {
+                let volume = Volume::new(args.voxel_size, bb_front_top_left, bb_back_bottom_right)?;
+                (volume, Progress::default())
+            }
+        };
+        // A resumed volume has been through the hull already
+        if args.hull_first && args.mode != Mode::VisualHull && args.resume.is_none() {
+            visual_hull::carve(&mut volume, &views)?;
+        }
+        match args.mode {
+            Mode::SpaceCarving => {
+                let checkpoints = (args.checkpoint.is_some() || args.checkpoint_interval.is_some())
+                    .then(|| Checkpoints {
+                        filename: args.checkpoint.clone().unwrap_or_else(|| {
+                            Path::new(&args.output)
+                                .with_extension("volume")
+                                .to_string_lossy()
+                                .into_owned()
+                        }),
+                        interval: args
+                            .checkpoint_interval
+                            .map_or(checkpoint::DEFAULT_INTERVAL, Duration::from_secs),
+                    });
+                carve::carve_checkpointed(
+                    &mut volume,
+                    &mut views,
+                    check,
+                    progress,
+                    checkpoints.as_ref(),
+                )?
+            }
+            Mode::Gvc => gvc::carve(&mut volume, &views, check)?,
+            Mode::VisualHull => visual_hull::carve(&mut volume, &views)?,
+            Mode::Probabilistic => {
+                let occupancy =
+                    probabilistic::carve(&mut volume, &mut views, args.noise, args.carve_log_odds)?;
+                // Don't overwrite the output if it's a NRRD volume too
+                let path = args.occupancy.clone().unwrap_or_else(|| {
+                    let extension = match exporter::Format::from_path(&args.output) {
+                        Some(exporter::Format::Nrrd) => "occupancy.nrrd",
+                        _ => "nrrd",
+                    };
+                    Path::new(&args.output)
+                        .with_extension(extension)
+                        .to_string_lossy()
+                        .into_owned()
+                });
+                exporter::write_nrrd(&volume, &occupancy.probabilities(), &path)?;
+            }
+        }
+        volume
+    };
+
+    if let Some(robust) = &robust {
+        for (name, rejections) in robust.rejections() {
+            if rejections.rejected > 0 {
+                println!(
+                    "{} was rejected as an outlier in {} of {} checks ({:.1}%)",
+                    name,
+                    rejections.rejected,
+                    rejections.checked,
+                    100.0 * rejections.rejected as f32 / rejections.checked as f32
+                );
+            }
+        }
+    }
+
+    export(&args, &volume)
+}
+
+/// write `volume` to the output file in the format and with the meshing
+/// method `args` ask for
+fn export(args: &Args, volume: &Volume) -> Result<()> {
+    // Output the result. Voxel formats and cubes as ply are written straight
+    // from the volume, and every other mesh and format goes through
+    // `mesh::Mesh`
+    let format = args
+        .format
+        .or_else(|| exporter::Format::from_path(&args.output))
+        .unwrap_or(exporter::Format::Ply);
+    match format {
+        exporter::Format::Vox => return exporter::write_vox(volume, &args.output),
+        exporter::Format::Nrrd => return exporter::write_volume_nrrd(volume, &args.output),
+        _ => {}
+    }
+    // stl is mostly used for printing, which needs a closed surface
+    let method = args.mesh.unwrap_or(match format {
+        exporter::Format::Stl => mesh::Method::Faces,
+        _ => mesh::Method::Cubes,
+    });
+    let surface = match method {
+        mesh::Method::Cubes if format == exporter::Format::Ply => {
+            return exporter::write_ply(volume, &args.output, args.ply_encoding)
+        }
+        mesh::Method::Cubes => mesh::cubes(volume),
+        mesh::Method::Faces => mesh::exposed_faces(volume, false),
+        mesh::Method::Greedy => mesh::exposed_faces(volume, true),
+        mesh::Method::SurfaceNets => mesh::surface_nets(volume),
+        mesh::Method::Points => mesh::point_cloud(volume),
+    };
+    if method == mesh::Method::Points {
+        println!("Sampled {} surface points", surface.positions.len());
+    } else {
+        println!(
+            "Meshed the surface with {} vertices and {} faces",
+            surface.positions.len(),
+            surface.face_count()
+        );
+    }
+    exporter::write_mesh(&surface, &args.output, format, args.ply_encoding)
+}
+
+fn main() {
+    let args = Args::parse();
+    let conflict = |flag: &str| -> ! {
+        Args::into_app()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!("{} can't be used with --mode {}", flag, args.mode),
+            )
+            .exit()
+    };
+    // Only space-carving carves octrees and saves its progress
+    let space_carving_only = [
+        ("--levels", args.levels > 1),
+        ("--checkpoint", args.checkpoint.is_some()),
+        ("--checkpoint-interval", args.checkpoint_interval.is_some()),
+        ("--resume", args.resume.is_some()),
+    ];
+    for (flag, given) in space_carving_only {
+        if given && args.mode != Mode::SpaceCarving {
+            conflict(flag);
+        }
+    }
+    // The probabilistic mode weighs the views' colors itself instead of
+    // running a consistency check
+    let check_options = [
+        ("--threshold", args.threshold.is_some()),
+        ("--consistency", args.consistency.is_some()),
+        ("--robust", args.robust),
+    ];
+    for (flag, given) in check_options {
+        if given && args.mode == Mode::Probabilistic {
+            conflict(flag);
+        }
+    }
+
+    let pool = match rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()
+    {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("error: couldn't create thread pool: {}", e);
+            process::exit(1);
+        }
+    };
 
-    // Output the result
-    exporter::write_ply(&mut volume, &args.output);
+    if let Err(e) = pool.install(|| run(args)) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }
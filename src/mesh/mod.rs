@@ -0,0 +1,415 @@
+//! Turning a carved volume into a polygon mesh. Rather than drawing a cube
+//! for every surviving voxel, only the faces between a voxel that's still
+//! there and one that's been carved (or the outside of the volume) are kept,
+//! and faces share the vertices they have in common. The result is a closed
+//! surface with a fraction of the vertices and faces. `surface_nets` goes a
+//! step further and smooths the voxel steps away.
+use crate::volume::{Color, Volume, Voxel};
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+mod surface_nets;
+
+pub use surface_nets::surface_nets;
+
+/// How to turn the carved volume into a mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// a separate cube for every surface voxel (`cubes`)
+    Cubes,
+    /// only the exposed faces of the voxels, sharing vertices (`exposed_faces`)
+    Faces,
+    /// like `Faces`, with runs of coplanar faces of the same color merged into
+    /// larger rectangles. Not watertight (see `exposed_faces`)
+    Greedy,
+    /// a smooth triangle mesh with vertex normals and colors (`surface_nets`)
+    SurfaceNets,
+    /// a point cloud with a point per surface voxel, and no faces
+    /// (`point_cloud`)
+    Points,
+}
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cubes" => Ok(Method::Cubes),
+            "faces" => Ok(Method::Faces),
+            "greedy" => Ok(Method::Greedy),
+            "surface-nets" => Ok(Method::SurfaceNets),
+            "points" => Ok(Method::Points),
+            _ => Err(format!(
+                "unknown mesh method `{}`, expected one of: cubes, faces, greedy, surface-nets, points",
+                s
+            )),
+        }
+    }
+}
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::Cubes => write!(f, "cubes"),
+            Method::Faces => write!(f, "faces"),
+            Method::Greedy => write!(f, "greedy"),
+            Method::SurfaceNets => write!(f, "surface-nets"),
+            Method::Points => write!(f, "points"),
+        }
+    }
+}
+
+/// An indexed polygon mesh where every face has the same number of vertices.
+/// Faces are wound counter-clockwise when seen from outside.
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<glm::Vec3>,
+    /// the vertex indices of every face, `face_size` at a time
+    pub indices: Vec<u32>,
+    /// the number of vertices of each face, e.g. 4 for quads
+    pub face_size: usize,
+    /// one color per face, or empty
+    pub face_colors: Vec<Color>,
+    /// one unit normal per vertex, or empty
+    pub normals: Vec<glm::Vec3>,
+    /// one color per vertex, or empty
+    pub vertex_colors: Vec<Color>,
+}
+impl Mesh {
+    /// the number of faces in the mesh
+    pub fn face_count(&self) -> usize {
+        self.indices.len().checked_div(self.face_size).unwrap_or(0)
+    }
+    /// the vertex indices of every face
+    pub fn faces(&self) -> impl Iterator<Item = &[u32]> {
+        self.indices.chunks(self.face_size.max(1))
+    }
+    /// the same mesh with every face split into a fan of triangles. Face
+    /// colors carry over to every triangle of the face.
+    pub fn triangulated(&self) -> Mesh {
+        if self.face_size <= 3 {
+            return self.clone();
+        }
+
+        let fan = self.face_size - 2;
+        let mut indices = Vec::with_capacity(self.face_count() * fan * 3);
+        for face in self.faces() {
+            for i in 1..=fan {
+                indices.extend([face[0], face[i], face[i + 1]]);
+            }
+        }
+        Mesh {
+            indices,
+            face_size: 3,
+            face_colors: self
+                .face_colors
+                .iter()
+                .flat_map(|&color| std::iter::repeat_n(color, fan))
+                .collect(),
+            ..self.clone()
+        }
+    }
+    /// the same mesh with separate vertices for every face, so that each face
+    /// can have its own normal and color. Vertices get the normal of their
+    /// face, and its color if the mesh has face colors.
+    pub fn flat_shaded(&self) -> Mesh {
+        let mut mesh = Mesh {
+            face_size: self.face_size,
+            ..Mesh::default()
+        };
+        for (i, face) in self.faces().enumerate() {
+            // Newell's method, which also works for faces that aren't
+            // quite flat
+            let corner = |j: usize| self.positions[face[j % face.len()] as usize];
+            let normal = (0..face.len()).fold(glm::vec3(0.0, 0.0, 0.0), |sum, j| {
+                sum + glm::cross(&corner(j), &corner(j + 1))
+            });
+            let normal = if glm::length(&normal) > 0.0 {
+                glm::normalize(&normal)
+            } else {
+                normal
+            };
+
+            for &index in face {
+                mesh.indices.push(mesh.positions.len() as u32);
+                mesh.positions.push(self.positions[index as usize]);
+                mesh.normals.push(normal);
+                if let Some(&color) = self.face_colors.get(i) {
+                    mesh.vertex_colors.push(color);
+                } else if let Some(&color) = self.vertex_colors.get(index as usize) {
+                    mesh.vertex_colors.push(color);
+                }
+            }
+        }
+        mesh
+    }
+}
+
+/// the color a surface voxel is drawn in. Untouched voxels are drawn in
+/// magenta, like in `exporter::write_ply`.
+fn surface_color(voxel: Voxel) -> Option<Color> {
+    match voxel {
+        Voxel::Colored(color) => Some(color),
+        Voxel::Untouched => Some(Color::new(1.0, 0.0, 1.0)),
+        Voxel::Carved => None,
+    }
+}
+
+/// A separate cube for every surface voxel, like `exporter::write_ply`, as a
+/// quad mesh with one color per face. Cubes don't share vertices.
+pub fn cubes(volume: &Volume) -> Mesh {
+    // The corners of each cube in the order of `Volume::voxel_corners`, where
+    // bits 0, 1 and 2 of the index pick the +x, +y and +z side, and its faces
+    // wound counter-clockwise from outside: +x, -x, +y, -y, +z, -z
+    const FACES: [[u32; 4]; 6] = [
+        [1, 3, 7, 5],
+        [0, 4, 6, 2],
+        [2, 6, 7, 3],
+        [0, 1, 5, 4],
+        [4, 5, 7, 6],
+        [0, 2, 3, 1],
+    ];
+
+    let mut mesh = Mesh {
+        face_size: 4,
+        ..Mesh::default()
+    };
+    for ((x, y, z), voxel) in volume.voxels() {
+        // Only render untouched voxels if they're on the surface of the volume
+        let color = match voxel {
+            Voxel::Untouched if !matches!(volume.voxel_visible(x, y, z), Ok(true)) => continue,
+            voxel => match surface_color(voxel) {
+                Some(color) => color,
+                None => continue,
+            },
+        };
+
+        let base = mesh.positions.len() as u32;
+        mesh.positions.extend(volume.voxel_corners(x, y, z));
+        for face in FACES {
+            mesh.indices.extend(face.map(|corner| base + corner));
+            mesh.face_colors.push(color);
+        }
+    }
+    mesh
+}
+
+/// how far around a surface voxel `point_cloud` looks to estimate its normal,
+/// in voxels
+const NORMAL_RADIUS: isize = 2;
+
+/// A point cloud with a point at the center of every surface voxel, i.e.
+/// every voxel next to a carved one or the outside of the volume, with the
+/// voxel's color and an estimated normal. The normal points away from the
+/// kept voxels around the point, towards the average offset of the carved
+/// ones within `NORMAL_RADIUS` voxels. The mesh has no faces.
+pub fn point_cloud(volume: &Volume) -> Mesh {
+    let kept = |x: isize, y: isize, z: isize| {
+        x >= 0
+            && y >= 0
+            && z >= 0
+            && (x as usize) < volume.width
+            && (y as usize) < volume.height
+            && (z as usize) < volume.depth
+            && !volume.is_carved(x as usize, y as usize, z as usize)
+    };
+
+    let mut mesh = Mesh::default();
+    for ((x, y, z), voxel) in volume.voxels() {
+        let color = match surface_color(voxel) {
+            Some(color) if matches!(volume.voxel_visible(x, y, z), Ok(true)) => color,
+            _ => continue,
+        };
+
+        let (cx, cy, cz) = (x as isize, y as isize, z as isize);
+        let mut direction = glm::vec3(0.0, 0.0, 0.0);
+        for dz in -NORMAL_RADIUS..=NORMAL_RADIUS {
+            for dy in -NORMAL_RADIUS..=NORMAL_RADIUS {
+                for dx in -NORMAL_RADIUS..=NORMAL_RADIUS {
+                    if !kept(cx + dx, cy + dy, cz + dz) {
+                        direction += glm::vec3(dx as f32, dy as f32, dz as f32);
+                    }
+                }
+            }
+        }
+        // Voxel indices grow towards +x, -y and -z in scene space
+        let normal = glm::vec3(direction.x, -direction.y, -direction.z);
+        let normal = if glm::length(&normal) > 0.0 {
+            glm::normalize(&normal)
+        } else {
+            normal
+        };
+
+        mesh.positions.push(volume.voxel_to_position(x, y, z));
+        mesh.normals.push(normal);
+        mesh.vertex_colors.push(color);
+    }
+    mesh
+}
+
+/// Builds a mesh out of quads on the voxel lattice, sharing the vertices of
+/// neighboring quads
+struct Builder<'a> {
+    volume: &'a Volume,
+    mesh: Mesh,
+    vertices: HashMap<[usize; 3], u32>,
+}
+impl<'a> Builder<'a> {
+    fn new(volume: &'a Volume) -> Self {
+        Builder {
+            volume,
+            mesh: Mesh {
+                face_size: 4,
+                ..Mesh::default()
+            },
+            vertices: HashMap::new(),
+        }
+    }
+    /// the index of the vertex at the lattice point `corner`, i.e. the corner
+    /// of voxel `corner` with the smallest indices
+    fn vertex(&mut self, corner: [usize; 3]) -> u32 {
+        let volume = self.volume;
+        let positions = &mut self.mesh.positions;
+        *self.vertices.entry(corner).or_insert_with(|| {
+            let s = volume.voxel_size;
+            // Voxel indices grow towards +x, -y and -z in scene space, which
+            // flips two axes and so keeps the winding of faces
+            positions.push(
+                volume.front_top_left
+                    + glm::vec3(
+                        corner[0] as f32 * s,
+                        -(corner[1] as f32) * s,
+                        -(corner[2] as f32) * s,
+                    ),
+            );
+            (positions.len() - 1) as u32
+        })
+    }
+    /// add the rectangle in the lattice plane `layer` along `axis`, covering
+    /// `width` by `height` voxels from `(u, v)` in the other two axes (in
+    /// cyclic order). It faces towards the positive end of `axis` if `positive`.
+    #[allow(clippy::too_many_arguments)]
+    fn quad(
+        &mut self,
+        axis: usize,
+        layer: usize,
+        (u, v): (usize, usize),
+        (width, height): (usize, usize),
+        positive: bool,
+        color: Color,
+    ) {
+        let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+        let corner = |du: usize, dv: usize| {
+            let mut point = [0; 3];
+            point[axis] = layer;
+            point[u_axis] = u + du;
+            point[v_axis] = v + dv;
+            point
+        };
+
+        // u x v points along +axis, so this order is counter-clockwise seen
+        // from the positive side
+        let mut corners = [
+            corner(0, 0),
+            corner(width, 0),
+            corner(width, height),
+            corner(0, height),
+        ];
+        if !positive {
+            corners.reverse();
+        }
+        for corner in corners {
+            let index = self.vertex(corner);
+            self.mesh.indices.push(index);
+        }
+        self.mesh.face_colors.push(color);
+    }
+}
+
+/// The exposed faces of `volume` as a quad mesh with one color per face, taken
+/// from the voxel the face belongs to. Vertices are shared between faces, so
+/// the mesh is closed. If `greedy`, neighboring faces in the same plane with
+/// the same color and facing the same way are merged into rectangles, which
+/// makes flat areas far smaller. The corners of one rectangle can then lie in
+/// the middle of another's edge (a T-junction), so the greedy mesh isn't
+/// watertight and can show hairline cracks or fail printing checks.
+pub fn exposed_faces(volume: &Volume, greedy: bool) -> Mesh {
+    let mut builder = Builder::new(volume);
+    let dims = [volume.width, volume.height, volume.depth];
+
+    for axis in 0..3 {
+        let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+        let (width, height) = (dims[u_axis], dims[v_axis]);
+        let voxel = |a: usize, u: usize, v: usize| {
+            let mut p = [0; 3];
+            p[axis] = a;
+            p[u_axis] = u;
+            p[v_axis] = v;
+            volume.get(p[0], p[1], p[2])
+        };
+
+        // Walk the lattice planes between the voxel layers along this axis,
+        // recording which way each face in the plane points and its color
+        let mut mask: Vec<Option<(bool, [u8; 3])>> = vec![None; width * height];
+        for layer in 0..=dims[axis] {
+            for v in 0..height {
+                for u in 0..width {
+                    let before = (layer > 0).then(|| voxel(layer - 1, u, v));
+                    let after = (layer < dims[axis]).then(|| voxel(layer, u, v));
+                    let before = before.and_then(surface_color);
+                    let after = after.and_then(surface_color);
+                    mask[u + width * v] = match (before, after) {
+                        (Some(color), None) => Some((true, color.to_rgb8())),
+                        (None, Some(color)) => Some((false, color.to_rgb8())),
+                        _ => None,
+                    };
+                }
+            }
+
+            for v in 0..height {
+                let mut u = 0;
+                while u < width {
+                    let face = match mask[u + width * v] {
+                        Some(face) => face,
+                        None => {
+                            u += 1;
+                            continue;
+                        }
+                    };
+
+                    // Grow the rectangle along u, then along v for as long as
+                    // every face in the next row matches
+                    let (mut w, mut h) = (1, 1);
+                    if greedy {
+                        while u + w < width && mask[u + w + width * v] == Some(face) {
+                            w += 1;
+                        }
+                        while v + h < height
+                            && (u..u + w).all(|x| mask[x + width * (v + h)] == Some(face))
+                        {
+                            h += 1;
+                        }
+                    }
+                    for y in v..v + h {
+                        for x in u..u + w {
+                            mask[x + width * y] = None;
+                        }
+                    }
+
+                    let (positive, color) = face;
+                    builder.quad(
+                        axis,
+                        layer,
+                        (u, v),
+                        (w, h),
+                        positive,
+                        Color::from_rgb8(color),
+                    );
+                    u += w;
+                }
+            }
+        }
+    }
+
+    builder.mesh
+}
@@ -0,0 +1,330 @@
+//! Surface nets, a smooth alternative to the voxel faces. The dual lattice
+//! has a cell for every cube between eight neighboring voxel centers, and
+//! every cell with both kept and carved voxels at its corners gets a vertex.
+//! The four cells around each edge between a kept and a carved voxel are then
+//! joined into a quad. A carved volume is binary, so the vertices start out at
+//! the average of the midpoints of their cell's crossing edges, which still
+//! shows the voxel steps. They're then relaxed towards their neighbors while
+//! staying inside their own cell, as in Gibson's constrained elastic surface
+//! nets. That keeps the surface within a voxel of the carved result, and
+//! unlike blurring the volume first, thin parts don't disappear.
+use super::{surface_color, Mesh};
+use crate::volume::{Color, Volume};
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+
+/// how many times the vertices are relaxed towards their neighbors
+const SMOOTHING_ITERATIONS: usize = 10;
+
+/// the edges of a cell as pairs of corners, where bits 0, 1 and 2 of a corner
+/// pick its offset along the x, y and z axes
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// the offset of a corner from the first corner of its cell
+fn corner_offset(corner: usize) -> [usize; 3] {
+    [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1]
+}
+
+/// the position of a corner in a cell, from 0 to 1 along each axis
+fn corner_position(corner: usize) -> glm::Vec3 {
+    let [x, y, z] = corner_offset(corner);
+    glm::vec3(x as f32, y as f32, z as f32)
+}
+
+/// A vertex of the net
+struct Vertex {
+    /// the cell the vertex is in
+    cell: [usize; 3],
+    /// the colors of the voxels at the corners of the cell, `None` if carved
+    corners: [Option<Color>; 8],
+}
+
+/// The surface of `volume` as a smooth triangle mesh with surface nets, with
+/// a normal and a color for every vertex. Colors are interpolated from the
+/// kept voxels around each vertex, with untouched voxels counting as magenta
+/// like in the other meshes.
+pub fn surface_nets(volume: &Volume) -> Mesh {
+    let dims = [volume.width, volume.height, volume.depth];
+
+    // Cell c spans the voxel centers c - 1 to c along each axis, so there's a
+    // border of cells around the volume and the net is closed. Positions are
+    // in voxel indices until the end.
+    let corners = |cell: [usize; 3]| -> [Option<Color>; 8] {
+        std::array::from_fn(|corner| {
+            let offset = corner_offset(corner);
+            let mut voxel = [0; 3];
+            for axis in 0..3 {
+                voxel[axis] = (cell[axis] + offset[axis])
+                    .checked_sub(1)
+                    .filter(|&i| i < dims[axis])?;
+            }
+            surface_color(volume.get(voxel[0], voxel[1], voxel[2]))
+        })
+    };
+    let cell_origin = |cell: [usize; 3]| {
+        glm::vec3(cell[0] as f32, cell[1] as f32, cell[2] as f32) - glm::vec3(1.0, 1.0, 1.0)
+    };
+
+    let mut vertices = vec![];
+    let mut positions = vec![];
+    let mut cells = HashMap::new();
+    for z in 0..=dims[2] {
+        for y in 0..=dims[1] {
+            for x in 0..=dims[0] {
+                let cell = [x, y, z];
+                let corners = corners(cell);
+                let kept = corners.iter().filter(|c| c.is_some()).count();
+                if kept == 0 || kept == 8 {
+                    continue;
+                }
+
+                let (sum, crossings) = EDGES
+                    .iter()
+                    .filter(|(a, b)| corners[*a].is_some() != corners[*b].is_some())
+                    .fold((glm::vec3(0.0, 0.0, 0.0), 0), |(sum, n), (a, b)| {
+                        (
+                            sum + (corner_position(*a) + corner_position(*b)) / 2.0,
+                            n + 1,
+                        )
+                    });
+                cells.insert(cell, vertices.len() as u32);
+                positions.push(cell_origin(cell) + sum / crossings as f32);
+                vertices.push(Vertex { cell, corners });
+            }
+        }
+    }
+
+    // Join the cells around every edge between a kept and a carved voxel,
+    // walking the lattice planes between voxel layers like `exposed_faces`
+    let mut quads = vec![];
+    for axis in 0..3 {
+        let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+        let kept = |a: usize, u: usize, v: usize| {
+            let mut p = [0; 3];
+            p[axis] = a;
+            p[u_axis] = u;
+            p[v_axis] = v;
+            !volume.is_carved(p[0], p[1], p[2])
+        };
+
+        for layer in 0..=dims[axis] {
+            for v in 0..dims[v_axis] {
+                for u in 0..dims[u_axis] {
+                    let before = layer > 0 && kept(layer - 1, u, v);
+                    let after = layer < dims[axis] && kept(layer, u, v);
+                    if before == after {
+                        continue;
+                    }
+
+                    let vertex = |du: usize, dv: usize| {
+                        let mut cell = [0; 3];
+                        cell[axis] = layer;
+                        cell[u_axis] = u + du;
+                        cell[v_axis] = v + dv;
+                        cells[&cell]
+                    };
+                    // Counter-clockwise seen from the positive side, like
+                    // `Builder::quad`
+                    let mut quad = [vertex(0, 0), vertex(1, 0), vertex(1, 1), vertex(0, 1)];
+                    if after {
+                        quad.reverse();
+                    }
+                    quads.push(quad);
+                }
+            }
+        }
+    }
+
+    // Relax every vertex towards the average of its neighbors, without
+    // leaving its cell
+    let mut neighbors = vec![vec![]; vertices.len()];
+    for quad in &quads {
+        for i in 0..4 {
+            let (a, b) = (quad[i], quad[(i + 1) % 4]);
+            neighbors[a as usize].push(b);
+            neighbors[b as usize].push(a);
+        }
+    }
+    for list in &mut neighbors {
+        list.sort_unstable();
+        list.dedup();
+    }
+    for _ in 0..SMOOTHING_ITERATIONS {
+        positions = (0..vertices.len())
+            .map(|i| {
+                let list = &neighbors[i];
+                let average = list.iter().fold(glm::vec3(0.0, 0.0, 0.0), |acc, &n| {
+                    acc + positions[n as usize]
+                }) / list.len() as f32;
+                let low = cell_origin(vertices[i].cell);
+                glm::clamp_vec(&average, &low, &(low + glm::vec3(1.0, 1.0, 1.0)))
+            })
+            .collect();
+    }
+
+    // Blend the colors of the kept corners, weighted by how close the vertex
+    // ended up to each of them
+    let vertex_colors = vertices
+        .iter()
+        .zip(&positions)
+        .map(|(vertex, position)| {
+            let t = position - cell_origin(vertex.cell);
+            let (sum, total) = vertex.corners.iter().enumerate().fold(
+                (glm::vec3(0.0, 0.0, 0.0), 0.0),
+                |(sum, total), (corner, color)| match color {
+                    Some(color) => {
+                        let offset = corner_offset(corner);
+                        let weight = (0..3)
+                            .map(|axis| if offset[axis] == 1 { t[axis] } else { 1.0 - t[axis] })
+                            .product::<f32>()
+                            // so a vertex right on a carved corner still gets a color
+                            + 1e-4;
+                        (
+                            sum + weight * glm::vec3(color.r, color.g, color.b),
+                            total + weight,
+                        )
+                    }
+                    None => (sum, total),
+                },
+            );
+            Color::from_vec3(sum / total)
+        })
+        .collect();
+
+    // Voxel indices grow towards +x, -y and -z in scene space, which flips
+    // two axes and so keeps the winding of faces
+    let s = volume.voxel_size;
+    let positions: Vec<glm::Vec3> = positions
+        .iter()
+        .map(|p| {
+            volume.front_top_left + glm::vec3((p.x + 0.5) * s, -(p.y + 0.5) * s, -(p.z + 0.5) * s)
+        })
+        .collect();
+
+    // Split every quad along its shorter diagonal, and sum the area weighted
+    // normals of the triangles around each vertex
+    let mut indices = Vec::with_capacity(quads.len() * 6);
+    let mut normals = vec![glm::vec3(0.0, 0.0, 0.0); positions.len()];
+    for [a, b, c, d] in quads {
+        let position = |i: u32| positions[i as usize];
+        let triangles = if glm::distance2(&position(a), &position(c))
+            <= glm::distance2(&position(b), &position(d))
+        {
+            [[a, b, c], [a, c, d]]
+        } else {
+            [[a, b, d], [b, c, d]]
+        };
+        for triangle in triangles {
+            let [p, q, r] = triangle.map(position);
+            let normal = glm::cross(&(q - p), &(r - p));
+            for i in triangle {
+                normals[i as usize] += normal;
+            }
+            indices.extend(triangle);
+        }
+    }
+    for normal in &mut normals {
+        if glm::length(normal) > 0.0 {
+            *normal = glm::normalize(normal);
+        }
+    }
+
+    Mesh {
+        positions,
+        indices,
+        face_size: 3,
+        face_colors: vec![],
+        normals,
+        vertex_colors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::Voxel;
+
+    /// a 2x2x2 volume with only one voxel left, colored red
+    fn single_voxel() -> Volume {
+        let mut volume =
+            Volume::new(1.0, glm::vec3(0.0, 2.0, 2.0), glm::vec3(2.0, 0.0, 0.0)).unwrap();
+        for i in 0..volume.len() {
+            let (x, y, z) = volume.coords(i);
+            volume.set(x, y, z, Voxel::Carved);
+        }
+        volume.set(0, 0, 0, Voxel::Colored(Color::new(1.0, 0.0, 0.0)));
+        volume
+    }
+
+    /// a ball of radius 0.7 in a 20x20x20 volume around the origin
+    fn ball() -> Volume {
+        let mut volume =
+            Volume::new(0.1, glm::vec3(-1.0, 1.0, 1.0), glm::vec3(1.0, -1.0, -1.0)).unwrap();
+        for i in 0..volume.len() {
+            let (x, y, z) = volume.coords(i);
+            if glm::length(&volume.voxel_to_position(x, y, z)) > 0.7 {
+                volume.set(x, y, z, Voxel::Carved);
+            }
+        }
+        volume
+    }
+
+    #[test]
+    fn wraps_a_voxel_in_a_closed_net() {
+        let mesh = surface_nets(&single_voxel());
+
+        // A vertex in each of the eight cells around the voxel, and two
+        // triangles for each of its faces
+        assert_eq!(mesh.positions.len(), 8);
+        assert_eq!(mesh.face_count(), 12);
+        assert_eq!(mesh.normals.len(), 8);
+        let center = glm::vec3(0.5, 1.5, 1.5);
+        for (position, color) in mesh.positions.iter().zip(&mesh.vertex_colors) {
+            assert!(glm::distance(position, &center) < 1.0);
+            assert_eq!(*color, Color::new(1.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn meshes_a_ball_as_a_closed_surface_facing_out() {
+        let mesh = surface_nets(&ball());
+        assert!(mesh.face_count() > 0);
+
+        // Every edge is shared by exactly two triangles, running in opposite
+        // directions
+        let mut edges = HashMap::new();
+        for face in mesh.faces() {
+            for i in 0..3 {
+                *edges.entry((face[i], face[(i + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+
+        // The surface stays within a voxel of the ball, and faces and normals
+        // point away from its center
+        for (position, normal) in mesh.positions.iter().zip(&mesh.normals) {
+            assert!((glm::length(position) - 0.7).abs() < 0.15);
+            assert!(glm::dot(position, normal) > 0.0);
+        }
+        for face in mesh.faces() {
+            let [a, b, c] = [face[0], face[1], face[2]].map(|i| mesh.positions[i as usize]);
+            assert!(glm::dot(&glm::cross(&(b - a), &(c - a)), &(a + b + c)) > 0.0);
+        }
+    }
+}
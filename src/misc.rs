@@ -1,34 +1,36 @@
+use crate::error::{CarvingError, Result};
+use crate::view::View;
+use crate::volume::Volume;
+use image::GenericImageView;
+use nalgebra_glm as glm;
+use std::io;
+
 /// This function is for debugging only. It reads in images and backprojects the bounding box points
 /// to the image so we can visualize the bounding box.
-pub fn visualize_bounding_boxes(volume: &mut Volume, views: &mut Vec<View>) {
-    let points_and_colors = vec![
+pub fn visualize_bounding_boxes(volume: &Volume, views: &[View]) -> Result<()> {
+    let points_and_colors = [
         ((0, 0, 0), [255, 0, 0]),
         ((volume.width, volume.height, volume.depth), [0, 0, 255]),
     ];
 
-    for (i, view) in views.iter_mut().enumerate() {
+    for (i, view) in views.iter().enumerate() {
         let mut copy = view.img.clone().into_rgb8();
 
         for (point, color) in points_and_colors.iter() {
             let voxel = glm::vec3(point.0 as i32, point.1 as i32, point.2 as i32);
 
-            let color = color.clone();
+            let color = *color;
 
             let position =
                 volume.voxel_to_position(voxel.x as usize, voxel.y as usize, voxel.z as usize);
 
-            let position = glm::vec4(
-                position[0] as f32,
-                position[1] as f32,
-                position[2] as f32,
-                1.0,
-            );
-            let back_projected: glm::Vec3 = view.camera.projection_matrix() * position;
-
-            let back_projected = glm::vec2(
-                back_projected.x / back_projected.z,
-                back_projected.y / back_projected.z,
-            );
+            let back_projected = match view.camera.project(&position) {
+                Some(p) => p,
+                None => {
+                    eprintln!("Back projected point is behind the camera");
+                    continue;
+                }
+            };
 
             let mut x = back_projected.x.floor() as i32;
             let mut y = back_projected.y.floor() as i32;
@@ -50,7 +52,10 @@ pub fn visualize_bounding_boxes(volume: &mut Volume, views: &mut Vec<View>) {
             *copy.get_pixel_mut(x as u32, y as u32 + 1) = image::Rgb(color);
             *copy.get_pixel_mut(x as u32 + 1, y as u32) = image::Rgb(color);
         }
-        copy.save(format!("tmp/{:0width$}.png", i, width = 4))
-            .unwrap();
+        let filename = format!("tmp/{:0width$}.png", i, width = 4);
+        copy.save(&filename)
+            .map_err(|e| CarvingError::io(&filename, io::Error::other(e)))?;
     }
+
+    Ok(())
 }
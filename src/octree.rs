@@ -0,0 +1,593 @@
+//! A sparse octree representation of a volume, and a coarse-to-fine carving
+//! driver built on it.
+//!
+//! Most of a dataset's bounding box is empty space or solid interior, but a
+//! dense `Volume` has to allocate every voxel up front. Coarse-to-fine carving
+//! instead carves a coarse grid, subdivides only the surface voxels that
+//! survive, and repeats down to the target resolution. Carved space and solid
+//! interior stay as large leaves, so memory and time scale with the surface
+//! area of the object rather than the volume of its bounding box.
+use crate::brdf::ConsistencyCheck;
+use crate::carve::{self, check_voxel, Grid, Verdict};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::{Axis, Volume, Voxel};
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// A node of the octree. Branches store the index of the first of their eight
+/// children, which are stored next to each other.
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    Leaf(Voxel),
+    Branch(usize),
+}
+
+/// A cubic octree of voxels whose finest level lines up exactly with a
+/// `Volume` of the same bounding box and voxel size. Cells are addressed by
+/// their depth (0 is the root) and their indices in the `2^depth` grid at that
+/// depth, using the same axis directions as `Volume`.
+pub struct Octree {
+    nodes: Vec<Node>,
+    /// the size of a voxel at the finest level
+    pub voxel_size: f32,
+    pub front_top_left: glm::Vec3,
+    pub back_bottom_right: glm::Vec3,
+    /// the depth of the finest level
+    pub max_depth: u32,
+    /// the dimensions of the equivalent dense `Volume`, in finest level voxels.
+    /// Cells beyond these are outside the bounding box.
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+}
+impl Octree {
+    /// create an octree covering the given bounding box with finest voxels of
+    /// size voxel_size. The tree starts out as a single untouched leaf.
+    pub fn new(
+        voxel_size: f32,
+        front_top_left: glm::Vec3,
+        back_bottom_right: glm::Vec3,
+    ) -> Result<Self> {
+        let (width, height, depth) =
+            Volume::dimensions_for(voxel_size, front_top_left, back_bottom_right)?;
+
+        let largest = width.max(height).max(depth);
+        let max_depth = largest.next_power_of_two().trailing_zeros();
+
+        println!(
+            "Created octree with depth {} covering {}x{}x{} voxels",
+            max_depth, width, height, depth
+        );
+
+        Ok(Octree {
+            nodes: vec![Node::Leaf(Voxel::Untouched)],
+            voxel_size,
+            front_top_left,
+            back_bottom_right,
+            max_depth,
+            width,
+            height,
+            depth,
+        })
+    }
+    /// the edge length of a cell at the given depth
+    pub fn cell_size(&self, depth: u32) -> f32 {
+        self.voxel_size * (1 << (self.max_depth - depth)) as f32
+    }
+    /// the number of cells along each axis at the given depth
+    pub fn cells_per_axis(&self, depth: u32) -> usize {
+        1 << depth
+    }
+    /// the scene-space position of the center of a cell
+    pub fn cell_to_position(&self, depth: u32, x: usize, y: usize, z: usize) -> glm::Vec3 {
+        let size = self.cell_size(depth);
+        glm::vec3(
+            self.front_top_left.x + (x as f32 + 0.5) * size,
+            self.front_top_left.y - (y as f32 + 0.5) * size,
+            self.front_top_left.z - (z as f32 + 0.5) * size,
+        )
+    }
+    /// the scene-space positions of the eight corners of a cell
+    pub fn cell_corners(&self, depth: u32, x: usize, y: usize, z: usize) -> [glm::Vec3; 8] {
+        let center = self.cell_to_position(depth, x, y, z);
+        let s = self.cell_size(depth) / 2.0;
+
+        [
+            center + glm::vec3(-s, -s, -s),
+            center + glm::vec3(s, -s, -s),
+            center + glm::vec3(-s, s, -s),
+            center + glm::vec3(s, s, -s),
+            center + glm::vec3(-s, -s, s),
+            center + glm::vec3(s, -s, s),
+            center + glm::vec3(-s, s, s),
+            center + glm::vec3(s, s, s),
+        ]
+    }
+    /// true if a cell lies entirely outside the bounding box
+    fn outside_bounds(&self, depth: u32, x: usize, y: usize, z: usize) -> bool {
+        let shift = self.max_depth - depth;
+        (x << shift) >= self.width || (y << shift) >= self.height || (z << shift) >= self.depth
+    }
+    /// the index of the node for a cell, or of the leaf containing it if the
+    /// tree isn't subdivided that far, along with that node's depth
+    fn locate(&self, depth: u32, x: usize, y: usize, z: usize) -> (usize, u32) {
+        let mut node = 0;
+        for level in 0..depth {
+            match self.nodes[node] {
+                Node::Leaf(_) => return (node, level),
+                Node::Branch(first_child) => {
+                    let shift = depth - level - 1;
+                    let child =
+                        ((x >> shift) & 1) | ((y >> shift) & 1) << 1 | ((z >> shift) & 1) << 2;
+                    node = first_child + child;
+                }
+            }
+        }
+        (node, depth)
+    }
+    /// the state of a cell. Cells inside a coarser leaf share its state.
+    pub fn get(&self, depth: u32, x: usize, y: usize, z: usize) -> Voxel {
+        match self.nodes[self.locate(depth, x, y, z).0] {
+            Node::Leaf(voxel) => voxel,
+            // Branches are only partially carved, so they're still present
+            Node::Branch(_) => Voxel::Untouched,
+        }
+    }
+    /// split a leaf into eight children with the same state
+    fn subdivide(&mut self, node: usize) {
+        if let Node::Leaf(voxel) = self.nodes[node] {
+            let first_child = self.nodes.len();
+            self.nodes.extend([Node::Leaf(voxel); 8].iter());
+            self.nodes[node] = Node::Branch(first_child);
+        }
+    }
+    /// set the state of a cell, subdividing coarser leaves as needed. Any
+    /// finer cells inside it are discarded.
+    pub fn set(&mut self, depth: u32, x: usize, y: usize, z: usize, voxel: Voxel) {
+        loop {
+            let (node, node_depth) = self.locate(depth, x, y, z);
+            if node_depth == depth {
+                // Children of a replaced branch are left unreachable in the
+                // node array; they're reclaimed by `compact`
+                self.nodes[node] = Node::Leaf(voxel);
+                return;
+            }
+            self.subdivide(node);
+        }
+    }
+    /// the indices of every leaf, as `(depth, x, y, z, state)`
+    pub fn leaves(&self) -> Vec<(u32, usize, usize, usize, Voxel)> {
+        let mut leaves = vec![];
+        let mut stack = vec![(0, 0, 0, 0, 0)];
+        while let Some((node, depth, x, y, z)) = stack.pop() {
+            match self.nodes[node] {
+                Node::Leaf(voxel) => leaves.push((depth, x, y, z, voxel)),
+                Node::Branch(first_child) => {
+                    for child in 0..8 {
+                        stack.push((
+                            first_child + child,
+                            depth + 1,
+                            2 * x + (child & 1),
+                            2 * y + ((child >> 1) & 1),
+                            2 * z + ((child >> 2) & 1),
+                        ));
+                    }
+                }
+            }
+        }
+        leaves
+    }
+    /// the number of nodes currently allocated
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    /// rebuild the node array without any unreachable nodes
+    pub fn compact(&mut self) {
+        let mut nodes = vec![self.nodes[0]];
+        let mut i = 0;
+        while i < nodes.len() {
+            if let Node::Branch(first_child) = nodes[i] {
+                let new_first_child = nodes.len();
+                nodes.extend_from_slice(&self.nodes[first_child..first_child + 8]);
+                nodes[i] = Node::Branch(new_first_child);
+            }
+            i += 1;
+        }
+        self.nodes = nodes;
+    }
+    /// expand the octree into a dense volume at the finest resolution, e.g. to
+    /// export it. The volume only covers the uncarved leaves rather than the
+    /// whole bounding box, so carved space doesn't need to be allocated.
+    pub fn to_volume(&self) -> Result<Volume> {
+        let leaves: Vec<_> = self
+            .leaves()
+            .into_iter()
+            .filter(|&(_, _, _, _, voxel)| voxel != Voxel::Carved)
+            .map(|(depth, x, y, z, voxel)| {
+                // the range of finest voxels the leaf covers on each axis
+                let shift = self.max_depth - depth;
+                let range =
+                    |i: usize, len: usize| (i << shift).min(len)..((i + 1) << shift).min(len);
+                (
+                    range(x, self.width),
+                    range(y, self.height),
+                    range(z, self.depth),
+                    voxel,
+                )
+            })
+            .filter(|(x, y, z, _)| !x.is_empty() && !y.is_empty() && !z.is_empty())
+            .collect();
+
+        // Find the finest voxels bounding every uncarved leaf
+        let (mut min, mut max) = ([usize::MAX; 3], [0; 3]);
+        for (x, y, z, _) in &leaves {
+            for (axis, range) in [x, y, z].iter().enumerate() {
+                min[axis] = min[axis].min(range.start);
+                max[axis] = max[axis].max(range.end);
+            }
+        }
+        if leaves.is_empty() {
+            min = [0; 3];
+            max = [1; 3];
+        }
+
+        let s = self.voxel_size;
+        let corner = |[x, y, z]: [usize; 3]| {
+            self.front_top_left + glm::vec3(x as f32 * s, -(y as f32) * s, -(z as f32) * s)
+        };
+        let mut volume = Volume::new(s, corner(min), corner(max))?;
+
+        // The volume may be rounded up past the leaves, so start from nothing
+        for i in 0..volume.len() {
+            let (x, y, z) = volume.coords(i);
+            volume.set(x, y, z, Voxel::Carved);
+        }
+        for (xs, ys, zs, voxel) in leaves {
+            for fz in zs {
+                for fy in ys.clone() {
+                    for fx in xs.clone() {
+                        volume.set(fx - min[0], fy - min[1], fz - min[2], voxel);
+                    }
+                }
+            }
+        }
+        Ok(volume)
+    }
+}
+
+/// The cells of one depth of an octree that are being carved. Only the listed
+/// cells are checked; everything else keeps the state of its leaf.
+pub struct OctreeLevel<'a> {
+    tree: &'a mut Octree,
+    depth: u32,
+    /// the active cells in each plane perpendicular to each axis, indexed as
+    /// `planes[axis.index()][a]`
+    planes: [Vec<Vec<(usize, usize, usize)>>; 3],
+}
+impl<'a> OctreeLevel<'a> {
+    /// prepare to carve `cells`, which must all be at `depth`
+    pub fn new(tree: &'a mut Octree, depth: u32, cells: &[(usize, usize, usize)]) -> Self {
+        let n = tree.cells_per_axis(depth);
+        let mut level = OctreeLevel {
+            tree,
+            depth,
+            planes: [vec![vec![]; n], vec![vec![]; n], vec![vec![]; n]],
+        };
+        for &(x, y, z) in cells {
+            level.activate(x, y, z);
+        }
+        level
+    }
+    fn activate(&mut self, x: usize, y: usize, z: usize) {
+        self.planes[0][x].push((x, y, z));
+        self.planes[1][y].push((x, y, z));
+        self.planes[2][z].push((x, y, z));
+    }
+    /// the cells sharing a face with a cell, skipping those outside the octree
+    fn neighbors(&self, x: usize, y: usize, z: usize) -> Vec<(usize, usize, usize)> {
+        let n = self.tree.cells_per_axis(self.depth);
+        let mut neighbors = vec![];
+        if x > 0 {
+            neighbors.push((x - 1, y, z));
+        }
+        if x + 1 < n {
+            neighbors.push((x + 1, y, z));
+        }
+        if y > 0 {
+            neighbors.push((x, y - 1, z));
+        }
+        if y + 1 < n {
+            neighbors.push((x, y + 1, z));
+        }
+        if z > 0 {
+            neighbors.push((x, y, z - 1));
+        }
+        if z + 1 < n {
+            neighbors.push((x, y, z + 1));
+        }
+        neighbors
+    }
+    /// split a coarser uncarved leaf containing the cell into cells at this
+    /// level and make them active. This is how interior leaves that become
+    /// exposed while carving get refined.
+    fn refine(&mut self, x: usize, y: usize, z: usize) {
+        let (node, node_depth) = self.tree.locate(self.depth, x, y, z);
+        let voxel = match self.tree.nodes[node] {
+            Node::Leaf(voxel) if node_depth < self.depth && voxel != Voxel::Carved => voxel,
+            _ => return,
+        };
+
+        let shift = self.depth - node_depth;
+        let (bx, by, bz) = (
+            (x >> shift) << shift,
+            (y >> shift) << shift,
+            (z >> shift) << shift,
+        );
+        for cz in bz..bz + (1 << shift) {
+            for cy in by..by + (1 << shift) {
+                for cx in bx..bx + (1 << shift) {
+                    if self.tree.outside_bounds(self.depth, cx, cy, cz) {
+                        self.tree.set(self.depth, cx, cy, cz, Voxel::Carved);
+                    } else {
+                        self.tree.set(self.depth, cx, cy, cz, voxel);
+                        self.activate(cx, cy, cz);
+                    }
+                }
+            }
+        }
+    }
+}
+impl Grid for OctreeLevel<'_> {
+    fn dimension(&self, _axis: Axis) -> usize {
+        self.tree.cells_per_axis(self.depth)
+    }
+    fn plane(&self, axis: Axis, a: usize) -> Vec<(usize, usize, usize)> {
+        self.planes[axis.index()][a].clone()
+    }
+    fn voxel_to_position(&self, x: usize, y: usize, z: usize) -> glm::Vec3 {
+        self.tree.cell_to_position(self.depth, x, y, z)
+    }
+    fn voxel_corners(&self, x: usize, y: usize, z: usize) -> [glm::Vec3; 8] {
+        self.tree.cell_corners(self.depth, x, y, z)
+    }
+    fn is_carved(&self, x: usize, y: usize, z: usize) -> bool {
+        self.tree.get(self.depth, x, y, z) == Voxel::Carved
+    }
+    fn voxel_visible(&self, x: usize, y: usize, z: usize) -> Result<bool> {
+        let neighbors = self.neighbors(x, y, z);
+
+        // If the cell is on the edge of the octree, it's visible
+        if neighbors.len() < 6 {
+            return Ok(true);
+        }
+
+        // If a neighboring cell (or the coarser leaf containing it) isn't
+        // present, then this cell is visible
+        Ok(neighbors
+            .iter()
+            .any(|&(x, y, z)| self.tree.get(self.depth, x, y, z) == Voxel::Carved))
+    }
+    fn set(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+        self.tree.set(self.depth, x, y, z, voxel);
+
+        // Carving a cell exposes its neighbors, so any of them that are still
+        // coarse need to be carved at this level too
+        if voxel == Voxel::Carved {
+            for (x, y, z) in self.neighbors(x, y, z) {
+                self.refine(x, y, z);
+            }
+        }
+    }
+}
+
+/// true if the cell with the given corners projects entirely onto background
+/// in any view, which means it can't contain any part of the object
+fn empty_cell(views: &[View], corners: &[glm::Vec3]) -> bool {
+    views.iter().any(|view| {
+        let footprint = view.footprint(corners);
+        !footprint.is_empty() && footprint.iter().all(|&(x, y)| view.is_background(x, y))
+    })
+}
+
+/// Find the surface cells of a coarse level that the consistency check rejected
+/// and that should be carved. A coarse cell that straddles the surface can fail
+/// the check even though part of it is occupied, so a rejected cell is only
+/// carved if it isn't connected through other rejected cells to any uncarved
+/// cell the check didn't reject. That only removes clutter lying apart from the
+/// object, and never eats into its surface.
+fn unsupported(
+    tree: &Octree,
+    depth: u32,
+    cells: &[(usize, usize, usize)],
+    verdicts: &[Verdict],
+) -> Vec<(usize, usize, usize)> {
+    let rejected: HashMap<(usize, usize, usize), usize> = cells
+        .iter()
+        .zip(verdicts)
+        .filter(|&(_, &verdict)| verdict == Verdict::Carve)
+        .enumerate()
+        .map(|(i, (&cell, _))| (cell, i))
+        .collect();
+    let mut cells = vec![(0, 0, 0); rejected.len()];
+    for (&cell, &i) in &rejected {
+        cells[i] = cell;
+    }
+
+    let n = tree.cells_per_axis(depth) as isize;
+    let neighbors = |(x, y, z): (usize, usize, usize)| {
+        let mut neighbors = vec![];
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (nx, ny, nz) = (x as isize + dx, y as isize + dy, z as isize + dz);
+                    if (dx, dy, dz) != (0, 0, 0)
+                        && (0..n).contains(&nx)
+                        && (0..n).contains(&ny)
+                        && (0..n).contains(&nz)
+                    {
+                        neighbors.push((nx as usize, ny as usize, nz as usize));
+                    }
+                }
+            }
+        }
+        neighbors
+    };
+
+    // Start from the rejected cells touching something that is staying, and
+    // spread through the rejected cells connected to them
+    let mut supported = vec![false; cells.len()];
+    let mut stack = vec![];
+    for (i, &cell) in cells.iter().enumerate() {
+        if neighbors(cell).into_iter().any(|(x, y, z)| {
+            !rejected.contains_key(&(x, y, z)) && tree.get(depth, x, y, z) != Voxel::Carved
+        }) {
+            supported[i] = true;
+            stack.push(i);
+        }
+    }
+    while let Some(i) = stack.pop() {
+        for neighbor in neighbors(cells[i]) {
+            if let Some(&j) = rejected.get(&neighbor) {
+                if !supported[j] {
+                    supported[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+    }
+
+    cells
+        .into_iter()
+        .zip(supported)
+        .filter(|&(_, supported)| !supported)
+        .map(|(cell, _)| cell)
+        .collect()
+}
+
+/// Carve an octree from coarse to fine. The tree is first split uniformly into
+/// cells `levels - 1` levels coarser than the finest voxels.
+///
+/// At each coarse level, cells that project entirely onto background in some
+/// view are carved, then the consistency check is run on the remaining surface
+/// cells. A coarse cell that straddles the surface sees a mix of the object and
+/// the background, so the check can reject it even though part of it is
+/// occupied. Rejected cells are therefore only carved when they aren't
+/// connected to anything else that stays (see `unsupported`). The surface cells
+/// that survive are split into eight children for the next level, and interior
+/// cells stay as solid coarse leaves. The finest level is carved with the usual
+/// plane sweeps, splitting coarse leaves as carving exposes them.
+pub fn carve(
+    tree: &mut Octree,
+    views: &mut [View],
+    check: &dyn ConsistencyCheck,
+    levels: u32,
+) -> Result<()> {
+    let start_depth = tree.max_depth.saturating_sub(levels.max(1) - 1);
+
+    // Split the tree uniformly down to the starting depth, carving cells that
+    // are entirely outside the bounding box
+    let n = tree.cells_per_axis(start_depth);
+    let mut cells = vec![];
+    for z in 0..n {
+        for y in 0..n {
+            for x in 0..n {
+                if tree.outside_bounds(start_depth, x, y, z) {
+                    tree.set(start_depth, x, y, z, Voxel::Carved);
+                } else {
+                    tree.set(start_depth, x, y, z, Voxel::Untouched);
+                    cells.push((x, y, z));
+                }
+            }
+        }
+    }
+
+    for depth in start_depth..tree.max_depth {
+        println!(
+            "Checking {} cells of size {} at depth {}",
+            cells.len(),
+            tree.cell_size(depth),
+            depth
+        );
+
+        // Whether a cell is empty doesn't depend on any other cell, so check
+        // them all in parallel
+        let tree_ref = &*tree;
+        let empty: Vec<bool> = cells
+            .par_iter()
+            .map(|&(x, y, z)| empty_cell(views, &tree_ref.cell_corners(depth, x, y, z)))
+            .collect();
+        for (&(x, y, z), &empty) in cells.iter().zip(&empty) {
+            if empty {
+                tree.set(depth, x, y, z, Voxel::Carved);
+            }
+        }
+        println!(
+            "Carved {} empty cells",
+            empty.iter().filter(|&&empty| empty).count()
+        );
+
+        let level = OctreeLevel::new(tree, depth, &[]);
+        let mut surface = vec![];
+        for &(x, y, z) in &cells {
+            if !level.is_carved(x, y, z) && level.voxel_visible(x, y, z)? {
+                surface.push((x, y, z));
+            }
+        }
+
+        // Check every surface cell, and carve the groups of rejected cells
+        // that don't touch a cell the check accepted
+        let shared: Vec<&View> = views.iter().collect();
+        let verdicts = surface
+            .par_iter()
+            .map(|&(x, y, z)| {
+                let voxel = glm::vec3(x as i32, y as i32, z as i32);
+                Ok(check_voxel(voxel, &level, &shared, check)?.0)
+            })
+            .collect::<Result<Vec<Verdict>>>()?;
+        let carved = unsupported(tree, depth, &surface, &verdicts);
+        for &(x, y, z) in &carved {
+            tree.set(depth, x, y, z, Voxel::Carved);
+        }
+        println!("Carved {} unsupported cells", carved.len());
+
+        // Split every surviving surface cell
+        let surface: Vec<_> = surface
+            .into_iter()
+            .filter(|&(x, y, z)| tree.get(depth, x, y, z) != Voxel::Carved)
+            .collect();
+
+        cells.clear();
+        for (x, y, z) in surface {
+            for child in 0..8 {
+                let (cx, cy, cz) = (
+                    2 * x + (child & 1),
+                    2 * y + ((child >> 1) & 1),
+                    2 * z + ((child >> 2) & 1),
+                );
+                if tree.outside_bounds(depth + 1, cx, cy, cz) {
+                    tree.set(depth + 1, cx, cy, cz, Voxel::Carved);
+                } else {
+                    tree.set(depth + 1, cx, cy, cz, Voxel::Untouched);
+                    cells.push((cx, cy, cz));
+                }
+            }
+        }
+        cells.sort_by_key(|&(x, y, z)| (z, y, x));
+    }
+
+    println!(
+        "Carving {} cells of size {} at depth {}",
+        cells.len(),
+        tree.voxel_size,
+        tree.max_depth
+    );
+    let depth = tree.max_depth;
+    carve::carve(&mut OctreeLevel::new(tree, depth, &cells), views, check)?;
+
+    tree.compact();
+    println!("Octree has {} nodes", tree.node_count());
+
+    Ok(())
+}
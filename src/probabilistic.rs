@@ -0,0 +1,167 @@
+//! Probabilistic space carving. Rather than making a hard keep or carve
+//! decision the first time a voxel is checked, every check adds the evidence
+//! of each view to the voxel's log-odds of being occupied, and a voxel is only
+//! carved once its log-odds drop below a negative threshold. A single check
+//! can't get there on its own, so a voxel is only removed once the evidence
+//! from several sweeps adds up, and the result is far less sensitive to the
+//! exact noise level. Views that see background are the exception: like in
+//! the other modes, they carve a voxel straight away.
+//!
+//! Alongside the binary result, the occupancy probability of every voxel is
+//! returned as an `Occupancy` field, which can be exported with
+//! `exporter::write_nrrd` to pick an iso-level after the fact.
+use crate::brdf::Sample;
+use crate::carve::{self, Verdict};
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::{Color, Volume};
+use nalgebra_glm as glm;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// the standard deviation of the color differences between views that see
+/// the same surface, if not given
+pub const DEFAULT_NOISE: f32 = 0.2;
+/// the log-odds a voxel has to drop below to be carved, if not given. Every
+/// check counts for at most `MAX_EVIDENCE`, so this takes three checks that
+/// all speak against the voxel.
+pub const DEFAULT_CARVE_LOG_ODDS: f32 = -5.0;
+
+/// the evidence from a check where a view sees background where the voxel
+/// projects
+const BACKGROUND_EVIDENCE: f32 = -MAX_LOG_ODDS;
+/// the most a single check can count for or against a voxel
+const MAX_EVIDENCE: f32 = 2.0;
+/// the bounds of a voxel's log-odds, so that no voxel gets so certain that
+/// later evidence can't change its mind
+const MAX_LOG_ODDS: f32 = 10.0;
+
+/// The log-odds of every voxel of a volume being occupied, indexed like the
+/// volume. Voxels that were never checked have log-odds 0, i.e. a probability
+/// of 0.5.
+pub struct Occupancy {
+    log_odds: Vec<AtomicU32>,
+}
+impl Occupancy {
+    fn new(len: usize) -> Self {
+        Occupancy {
+            log_odds: (0..len).map(|_| AtomicU32::new(0f32.to_bits())).collect(),
+        }
+    }
+    /// the log-odds of the voxel at `index` being occupied
+    pub fn log_odds(&self, index: usize) -> f32 {
+        f32::from_bits(self.log_odds[index].load(Ordering::Relaxed))
+    }
+    /// the probability of the voxel at `index` being occupied
+    pub fn probability(&self, index: usize) -> f32 {
+        1.0 / (1.0 + (-self.log_odds(index)).exp())
+    }
+    /// the probability of every voxel being occupied, indexed like the volume
+    pub fn probabilities(&self) -> Vec<f32> {
+        (0..self.log_odds.len())
+            .map(|i| self.probability(i))
+            .collect()
+    }
+    /// add evidence to a voxel, returning its new log-odds
+    fn update(&self, index: usize, evidence: f32) -> f32 {
+        let log_odds = (self.log_odds(index) + evidence).clamp(-MAX_LOG_ODDS, MAX_LOG_ODDS);
+        self.log_odds[index].store(log_odds.to_bits(), Ordering::Relaxed);
+        log_odds
+    }
+}
+
+/// the evidence that a check's samples give for a voxel being occupied, the
+/// average of each view's evidence. Views that see the voxel's color close to
+/// the consensus (the median of the views' mean colors) count in favor, with
+/// the log of the likelihood ratio of a half-normal distribution of
+/// differences with standard deviation `noise` for a surface against a
+/// uniform distribution for empty space. A view that sees background rules
+/// the voxel out.
+fn evidence(samples: &[Sample], noise: f32) -> f32 {
+    // Like the consistency checks, take an average color close to the average
+    // background color to mean that every view is seeing background
+    let (color_sum, background_sum) = samples.iter().fold(
+        (glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 0.0)),
+        |(c, b), s| (c + s.color, b + s.background_color),
+    );
+    if (color_sum - background_sum).abs().max() / (samples.len() as f32) < 0.2 {
+        return BACKGROUND_EVIDENCE;
+    }
+
+    let num_views = samples.iter().map(|s| s.view + 1).max().unwrap_or(0);
+    let mut sums = vec![(glm::vec3(0.0, 0.0, 0.0), 0, false); num_views];
+    for sample in samples {
+        let (sum, count, background) = &mut sums[sample.view];
+        *sum += sample.color;
+        *count += 1;
+        *background |= sample.background;
+    }
+    if sums.iter().any(|(_, _, background)| *background) {
+        return BACKGROUND_EVIDENCE;
+    }
+    let views: Vec<glm::Vec3> = sums
+        .into_iter()
+        .filter(|(_, count, _)| *count > 0)
+        .map(|(sum, count, _)| sum / count as f32)
+        .collect();
+
+    let mut consensus = glm::vec3(0.0, 0.0, 0.0);
+    for channel in 0..3 {
+        let mut values: Vec<f32> = views.iter().map(|mean| mean[channel]).collect();
+        values.sort_by(|a, b| a.total_cmp(b));
+        consensus[channel] = values[values.len() / 2];
+    }
+
+    // the log-likelihood ratio of a perfect match. Color differences are at
+    // most sqrt(3).
+    let perfect = (2.0 * 3f32.sqrt() / (noise * (2.0 * std::f32::consts::PI).sqrt())).ln();
+
+    let total: f32 = views
+        .iter()
+        .map(|mean| {
+            let difference = glm::distance(mean, &consensus);
+            (perfect - difference * difference / (2.0 * noise * noise))
+                .clamp(-MAX_EVIDENCE, MAX_EVIDENCE)
+        })
+        .sum();
+    total / views.len() as f32
+}
+
+/// Carve the volume with plane sweeps like `carve::carve`, accumulating the
+/// evidence for each voxel in its occupancy instead of deciding each check on
+/// its own. `noise` is the expected standard deviation of the color
+/// differences between views that see the same surface, and a voxel is carved
+/// once its log-odds drop below `carve_log_odds`, which should be well below
+/// 0. Returns the occupancy of every voxel.
+pub fn carve(
+    volume: &mut Volume,
+    views: &mut [View],
+    noise: f32,
+    carve_log_odds: f32,
+) -> Result<Occupancy> {
+    let occupancy = Occupancy::new(volume.len());
+
+    carve::carve_with(volume, views, &|voxel, volume, views| {
+        let (samples, used_pixels) = carve::gather_samples(voxel, volume, views);
+        if samples.is_empty() {
+            return Ok((Verdict::Unseen, used_pixels));
+        }
+
+        // Each voxel is checked at most once per round of a sweep, so nothing
+        // else touches its occupancy while it's updated
+        let index = volume.index(voxel.x as usize, voxel.y as usize, voxel.z as usize);
+        let log_odds = occupancy.update(index, evidence(&samples, noise));
+
+        let verdict = if log_odds < carve_log_odds {
+            Verdict::Carve
+        } else {
+            let mean = samples
+                .iter()
+                .fold(glm::vec3(0.0, 0.0, 0.0), |acc, s| acc + s.color)
+                / samples.len() as f32;
+            Verdict::Keep(Color::from_vec3(mean))
+        };
+        Ok((verdict, used_pixels))
+    })?;
+
+    Ok(occupancy)
+}
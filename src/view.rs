@@ -1,19 +1,145 @@
-use image::{DynamicImage, GenericImageView};
+use crate::brdf::Sample;
+use image::{DynamicImage, GenericImageView, Pixel};
 use nalgebra_glm as glm;
+use std::cmp::Ordering;
 
+/// Lens distortion. It's applied to the normalized image coordinates
+/// `(x/z, y/z)` of a point in camera space, before they're mapped to pixels by
+/// the intrinsic matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distortion {
+    /// the Brown-Conrady model used by OpenCV and COLMAP, with radial
+    /// (`k1`, `k2`, `k3`) and tangential (`p1`, `p2`) coefficients
+    BrownConrady {
+        k1: f32,
+        k2: f32,
+        k3: f32,
+        p1: f32,
+        p2: f32,
+    },
+    /// the equidistant fisheye model of OpenCV's `fisheye` module (Kannala
+    /// and Brandt), which distorts the angle `θ` from the optical axis to
+    /// `θ (1 + k1 θ² + k2 θ⁴ + k3 θ⁶ + k4 θ⁸)`
+    Fisheye { k1: f32, k2: f32, k3: f32, k4: f32 },
+}
+impl Distortion {
+    /// Brown-Conrady distortion with only the radial `k1` and `k2`
+    pub fn radial(k1: f32, k2: f32) -> Self {
+        Distortion::BrownConrady {
+            k1,
+            k2,
+            k3: 0.0,
+            p1: 0.0,
+            p2: 0.0,
+        }
+    }
+    /// true if this doesn't distort at all. Only a fisheye with all
+    /// coefficients zero still distorts, since it maps angles rather than
+    /// distances linearly.
+    pub fn is_identity(&self) -> bool {
+        matches!(
+            *self,
+            Distortion::BrownConrady { k1, k2, k3, p1, p2 }
+                if k1 == 0.0 && k2 == 0.0 && k3 == 0.0 && p1 == 0.0 && p2 == 0.0
+        )
+    }
+    /// distort normalized image coordinates
+    pub fn apply(&self, point: &glm::Vec2) -> glm::Vec2 {
+        let (x, y) = (point.x, point.y);
+        let r2 = x * x + y * y;
+        match *self {
+            Distortion::BrownConrady { k1, k2, k3, p1, p2 } => {
+                let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+                glm::vec2(
+                    x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+                    y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+                )
+            }
+            Distortion::Fisheye { .. } => {
+                let r = r2.sqrt();
+                if r < 1e-8 {
+                    return *point;
+                }
+                point * (self.fisheye_angle(r.atan()) / r)
+            }
+        }
+    }
+    /// undo `apply`. There's no closed form, so this iterates towards the
+    /// undistorted point, which converges for any sensible lens.
+    pub fn remove(&self, point: &glm::Vec2) -> glm::Vec2 {
+        match *self {
+            Distortion::BrownConrady { .. } => {
+                let mut undistorted = *point;
+                for _ in 0..20 {
+                    undistorted += point - self.apply(&undistorted);
+                }
+                undistorted
+            }
+            Distortion::Fisheye { k1, k2, k3, k4 } => {
+                let distorted = glm::length(point);
+                if distorted < 1e-8 {
+                    return *point;
+                }
+                // Newton's method on the distorted angle, starting from the
+                // undistorted guess
+                let mut theta = distorted;
+                for _ in 0..20 {
+                    let t2 = theta * theta;
+                    let slope =
+                        1.0 + t2 * (3.0 * k1 + t2 * (5.0 * k2 + t2 * (7.0 * k3 + t2 * 9.0 * k4)));
+                    if slope.abs() < 1e-8 {
+                        break;
+                    }
+                    theta -= (self.fisheye_angle(theta) - distorted) / slope;
+                }
+                point * (theta.tan() / distorted)
+            }
+        }
+    }
+    /// the distorted angle of a fisheye lens
+    fn fisheye_angle(&self, theta: f32) -> f32 {
+        match *self {
+            Distortion::Fisheye { k1, k2, k3, k4 } => {
+                let t2 = theta * theta;
+                theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4))))
+            }
+            Distortion::BrownConrady { .. } => theta,
+        }
+    }
+}
+
+/// The calibration of a single camera. Scene points are projected into the
+/// image by the matrix `K*[R t]`, with optional lens distortion in between.
+#[derive(Debug, Clone)]
 pub struct CameraData {
     k: glm::Mat3,
     r: glm::Mat3,
     t: glm::Vec3,
+    distortion: Option<Distortion>,
 }
 impl CameraData {
+    /// create camera data from the row-major intrinsic matrix `k`, row-major
+    /// rotation `r` and translation `t`
     pub fn new(k: &[f32], r: &[f32], t: &[f32]) -> Self {
         CameraData {
             k: glm::mat3(k[0], k[1], k[2], k[3], k[4], k[5], k[6], k[7], k[8]),
             r: glm::mat3(r[0], r[1], r[2], r[3], r[4], r[5], r[6], r[7], r[8]),
             t: glm::vec3(t[0], t[1], t[2]),
+            distortion: None,
         }
     }
+    /// the same camera with a distorting lens
+    pub fn with_distortion(mut self, distortion: Distortion) -> Self {
+        self.distortion = Some(distortion);
+        self
+    }
+    /// the lens distortion, if any
+    pub fn distortion(&self) -> Option<&Distortion> {
+        self.distortion.as_ref()
+    }
+    /// the 3x4 matrix `K*[R t]` mapping homogenous scene coordinates to
+    /// homogenous pixel coordinates. This ignores lens distortion, see
+    /// `project`.
     pub fn projection_matrix(&self) -> glm::Mat3x4 {
         let rt = glm::mat3x4(
             self.r[(0, 0)],
@@ -31,15 +157,80 @@ impl CameraData {
         );
         self.k * rt
     }
+    /// the translation vector `t`. Note that this is *not* the position of the
+    /// camera, see `center` for that.
     pub fn translation(&self) -> glm::Vec3 {
         self.t
     }
+    /// the position of the camera in scene space, `-Rᵀt`
+    pub fn center(&self) -> glm::Vec3 {
+        -(self.r.transpose() * self.t)
+    }
+    /// the unit direction the camera is looking in, in scene space
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::normalize(&(self.r.transpose() * glm::vec3(0.0, 0.0, 1.0)))
+    }
+    /// project a scene-space point to continuous pixel coordinates. Returns
+    /// `None` if the point is behind the camera.
+    pub fn project(&self, point: &glm::Vec3) -> Option<glm::Vec2> {
+        if let Some(distortion) = &self.distortion {
+            let camera_space = self.r * point + self.t;
+            if camera_space.z <= 0.0 {
+                return None;
+            }
+            let normalized = glm::vec2(
+                camera_space.x / camera_space.z,
+                camera_space.y / camera_space.z,
+            );
+            let distorted = distortion.apply(&normalized);
+            return Some((self.k * glm::vec3(distorted.x, distorted.y, 1.0)).xy());
+        }
+
+        let back_projected: glm::Vec3 =
+            self.projection_matrix() * glm::vec4(point.x, point.y, point.z, 1.0);
+
+        // Scale by `z` to account for back projection ambiguity
+        if back_projected.z <= 0.0 {
+            return None;
+        }
+        Some(glm::vec2(
+            back_projected.x / back_projected.z,
+            back_projected.y / back_projected.z,
+        ))
+    }
+    /// the inverse of the projection: the scene-space point that projects to
+    /// `pixel` and lies `depth` units in front of the camera along its
+    /// optical axis
+    pub fn unproject(&self, pixel: &glm::Vec2, depth: f32) -> glm::Vec3 {
+        let mut camera_space = glm::inverse(&self.k) * glm::vec3(pixel.x, pixel.y, 1.0);
+        if let Some(distortion) = &self.distortion {
+            let undistorted = distortion.remove(&camera_space.xy());
+            camera_space = glm::vec3(undistorted.x, undistorted.y, 1.0);
+        }
+        self.center() + self.r.transpose() * (camera_space * depth)
+    }
+    /// the unit direction of the ray leaving the camera center through `pixel`
+    pub fn pixel_ray(&self, pixel: &glm::Vec2) -> glm::Vec3 {
+        glm::normalize(&(self.unproject(pixel, 1.0) - self.center()))
+    }
 }
 
+/// An image along with the camera that captured it
 pub struct View {
+    /// the name of the image, e.g. `dinoR0001`, used when reporting on views
+    pub name: String,
     pub camera: CameraData,
     pub img: Box<DynamicImage>,
+    /// pixels that have already been matched to a scene element, indexed as
+    /// `mask[y][x]`
     pub mask: Vec<Vec<bool>>,
+    /// optional foreground mask, indexed as `silhouette[y][x]`. `true` pixels
+    /// belong to the object, `false` pixels are background.
+    pub silhouette: Option<Vec<Vec<bool>>>,
+    /// the color of the background wherever there's no `plate`
+    pub background_color: glm::Vec3,
+    /// optional photo of the empty scene taken from the same camera
+    pub plate: Option<Box<DynamicImage>>,
 }
 impl View {
     pub fn new(camera: CameraData, img: DynamicImage) -> Self {
@@ -49,9 +240,123 @@ impl View {
         }
 
         View {
+            name: String::new(),
             camera,
             img: Box::new(img),
             mask,
+            silhouette: None,
+            background_color: glm::vec3(0.0, 0.0, 0.0),
+            plate: None,
+        }
+    }
+    /// the color of the pixel at `(x, y)` with each channel in `[0, 1]`
+    pub fn color_at(&self, x: u32, y: u32) -> glm::Vec3 {
+        let pix = self.img.get_pixel(x, y);
+
+        // Convert color from [0,255] to [0,1]
+        glm::vec3(
+            pix.channels()[0] as f32 / 255.0,
+            pix.channels()[1] as f32 / 255.0,
+            pix.channels()[2] as f32 / 255.0,
+        )
+    }
+    /// the pixels covered by the projection of `points` (e.g. the corners of a
+    /// voxel), clipped to the image. A pixel is covered if its center lies
+    /// inside the convex hull of the projected points. If the projection is too
+    /// small to cover any pixel center, the pixel containing its centroid is
+    /// returned instead. Empty if any point is behind the camera.
+    pub fn footprint(&self, points: &[glm::Vec3]) -> Vec<(u32, u32)> {
+        let mut projected = Vec::with_capacity(points.len());
+        for point in points {
+            match self.camera.project(point) {
+                Some(p) => projected.push(p),
+                None => return vec![],
+            }
+        }
+        if projected.is_empty() {
+            return vec![];
+        }
+
+        let hull = convex_hull(projected.clone());
+
+        let mut min = glm::vec2(f32::INFINITY, f32::INFINITY);
+        let mut max = glm::vec2(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for p in &hull {
+            min = glm::min2(&min, p);
+            max = glm::max2(&max, p);
+        }
+
+        let width = self.img.width() as i64;
+        let height = self.img.height() as i64;
+
+        let x0 = (min.x.floor() as i64).max(0);
+        let y0 = (min.y.floor() as i64).max(0);
+        let x1 = (max.x.floor() as i64).min(width - 1);
+        let y1 = (max.y.floor() as i64).min(height - 1);
+
+        let mut pixels = vec![];
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let center = glm::vec2(x as f32 + 0.5, y as f32 + 0.5);
+                if inside_convex_polygon(&hull, &center) {
+                    pixels.push((x as u32, y as u32));
+                }
+            }
+        }
+
+        if pixels.is_empty() {
+            let centroid = projected.iter().fold(glm::vec2(0.0, 0.0), |acc, p| acc + p)
+                / projected.len() as f32;
+            let x = centroid.x.floor() as i64;
+            let y = centroid.y.floor() as i64;
+            if x >= 0 && x < width && y >= 0 && y < height {
+                pixels.push((x as u32, y as u32));
+            }
+        }
+
+        pixels
+    }
+    /// false only if this view has a silhouette and `(x, y)` is background in it
+    pub fn in_silhouette(&self, x: u32, y: u32) -> bool {
+        match &self.silhouette {
+            Some(silhouette) => silhouette[y as usize][x as usize],
+            None => true,
+        }
+    }
+    /// the color the background has at `(x, y)`
+    pub fn background_at(&self, x: u32, y: u32) -> glm::Vec3 {
+        match &self.plate {
+            Some(plate) => {
+                let pix = plate.get_pixel(x, y);
+                glm::vec3(
+                    pix.channels()[0] as f32 / 255.0,
+                    pix.channels()[1] as f32 / 255.0,
+                    pix.channels()[2] as f32 / 255.0,
+                )
+            }
+            None => self.background_color,
+        }
+    }
+    /// true if the pixel at `(x, y)` shows background. Views with a silhouette
+    /// use it, others treat pixels that exactly match the background as
+    /// background.
+    pub fn is_background(&self, x: u32, y: u32) -> bool {
+        match &self.silhouette {
+            Some(silhouette) => !silhouette[y as usize][x as usize],
+            None => self.color_at(x, y) == self.background_at(x, y),
+        }
+    }
+    /// the pixel at `(x, y)` as a sample for the consistency check. `index`
+    /// is this view's index in the views passed to the check, and `ray` is the
+    /// vector from the scene element to the camera.
+    pub fn sample(&self, index: usize, x: u32, y: u32, ray: glm::Vec3) -> Sample {
+        Sample {
+            color: self.color_at(x, y),
+            background: self.is_background(x, y),
+            background_color: self.background_at(x, y),
+            ray,
+            view: index,
+            pixel: (x, y),
         }
     }
     /// reset the mask to all false
@@ -63,3 +368,152 @@ impl View {
         }
     }
 }
+
+/// z component of the cross product of `a - o` and `b - o`. Positive if
+/// `o -> a -> b` turns counter-clockwise.
+fn cross(o: &glm::Vec2, a: &glm::Vec2, b: &glm::Vec2) -> f32 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+/// the convex hull of `points` in counter-clockwise order, via Andrew's
+/// monotone chain algorithm
+fn convex_hull(mut points: Vec<glm::Vec2>) -> Vec<glm::Vec2> {
+    points.sort_by(|a, b| {
+        a.x.partial_cmp(&b.x)
+            .unwrap_or(Ordering::Equal)
+            .then(a.y.partial_cmp(&b.y).unwrap_or(Ordering::Equal))
+    });
+    if points.len() < 3 {
+        return points;
+    }
+
+    let mut hull: Vec<glm::Vec2> = Vec::with_capacity(points.len() * 2);
+    // lower hull
+    for p in &points {
+        while hull.len() >= 2 && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0.0 {
+            hull.pop();
+        }
+        hull.push(*p);
+    }
+    // upper hull
+    let lower_len = hull.len() + 1;
+    for p in points.iter().rev().skip(1) {
+        while hull.len() >= lower_len
+            && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0.0
+        {
+            hull.pop();
+        }
+        hull.push(*p);
+    }
+    // the last point is the same as the first
+    hull.pop();
+
+    hull
+}
+
+/// true if `p` is inside (or on the edge of) the counter-clockwise convex
+/// polygon `polygon`
+fn inside_convex_polygon(polygon: &[glm::Vec2], p: &glm::Vec2) -> bool {
+    if polygon.len() < 3 {
+        return false;
+    }
+    (0..polygon.len()).all(|i| cross(&polygon[i], &polygon[(i + 1) % polygon.len()], p) >= 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a 640x480 camera at `(0, 0, -3)` looking down +z at the origin
+    fn camera(distortion: Distortion) -> CameraData {
+        CameraData::new(
+            &[500.0, 0.0, 320.0, 0.0, 520.0, 240.0, 0.0, 0.0, 1.0],
+            &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            &[0.0, 0.0, 3.0],
+        )
+        .with_distortion(distortion)
+    }
+
+    const LENSES: [Distortion; 3] = [
+        Distortion::BrownConrady {
+            k1: -0.2,
+            k2: 0.05,
+            k3: 0.01,
+            p1: 0.001,
+            p2: -0.002,
+        },
+        Distortion::Fisheye {
+            k1: 0.0,
+            k2: 0.0,
+            k3: 0.0,
+            k4: 0.0,
+        },
+        Distortion::Fisheye {
+            k1: -0.05,
+            k2: 0.01,
+            k3: -0.002,
+            k4: 0.0005,
+        },
+    ];
+
+    #[test]
+    fn removes_what_it_applies() {
+        for lens in LENSES {
+            for (x, y) in [(0.0, 0.0), (0.3, -0.2), (-0.5, 0.4), (0.6, 0.6)] {
+                let point = glm::vec2(x, y);
+                let round_trip = lens.remove(&lens.apply(&point));
+                assert!(
+                    glm::distance(&round_trip, &point) < 1e-4,
+                    "{:?} moved {:?} to {:?}",
+                    lens,
+                    point,
+                    round_trip
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn projects_what_it_unprojects() {
+        for lens in LENSES {
+            let camera = camera(lens);
+            for (x, y) in [(320.0, 240.0), (10.0, 15.0), (600.0, 300.0), (200.0, 470.0)] {
+                let pixel = glm::vec2(x, y);
+                let point = camera.unproject(&pixel, 2.5);
+                assert!((point.z - (-0.5)).abs() < 1e-4);
+                let round_trip = camera.project(&point).unwrap();
+                assert!(
+                    glm::distance(&round_trip, &pixel) < 1e-2,
+                    "{:?} moved {:?} to {:?}",
+                    lens,
+                    pixel,
+                    round_trip
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn distorts_only_off_axis() {
+        let plain = CameraData::new(
+            &[500.0, 0.0, 320.0, 0.0, 520.0, 240.0, 0.0, 0.0, 1.0],
+            &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            &[0.0, 0.0, 3.0],
+        );
+        let barrel = camera(Distortion::radial(-0.2, 0.0));
+        assert!(Distortion::radial(0.0, 0.0).is_identity());
+
+        // The center of the image is the same with and without distortion,
+        // and barrel distortion pulls everything else towards it
+        let center = glm::vec3(0.0, 0.0, 0.0);
+        assert_eq!(barrel.project(&center), plain.project(&center));
+        let corner = glm::vec3(1.0, 1.0, 0.0);
+        let (distorted, undistorted) = (
+            barrel.project(&corner).unwrap(),
+            plain.project(&corner).unwrap(),
+        );
+        let middle = glm::vec2(320.0, 240.0);
+        assert!(glm::distance(&distorted, &middle) < glm::distance(&undistorted, &middle));
+        assert!(barrel.project(&glm::vec3(0.0, 0.0, -4.0)).is_none());
+    }
+}
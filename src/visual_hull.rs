@@ -0,0 +1,51 @@
+//! Silhouette-only carving. The visual hull is the largest volume whose
+//! projection stays inside every view's silhouette, so it ignores color
+//! entirely. That makes it robust to unreliable colors and fast enough to be
+//! used as a first pass before photo-consistency carving.
+use crate::error::Result;
+use crate::view::View;
+use crate::volume::{Axis, Volume, Voxel};
+use rayon::prelude::*;
+
+/// true if the voxel with the given corners projects entirely onto background
+/// in `view`. Views that don't see the voxel at all can't rule it out.
+fn outside_silhouette(view: &View, corners: &[nalgebra_glm::Vec3]) -> bool {
+    let footprint = view.footprint(corners);
+    !footprint.is_empty() && footprint.iter().all(|&(x, y)| !view.in_silhouette(x, y))
+}
+
+/// Carve every voxel whose projection falls outside the silhouette of any view
+/// that has one. Surviving voxels are left untouched. Views loaded with
+/// `importer::load_views` always have a silhouette, from a mask, the image's
+/// alpha channel or the background model.
+pub fn carve(volume: &mut Volume, views: &[View]) -> Result<()> {
+    let views: Vec<&View> = views.iter().filter(|v| v.silhouette.is_some()).collect();
+
+    // Each voxel is independent of every other, so carve slices of the volume
+    // in parallel
+    let volume_ref = &*volume;
+    let carved: Vec<Vec<(usize, usize, usize)>> = (0..volume.depth)
+        .into_par_iter()
+        .map(|z| {
+            volume_ref
+                .slice(Axis::Z, z)
+                .filter(|&(x, y, z)| {
+                    !volume_ref.is_carved(x, y, z) && {
+                        let corners = volume_ref.voxel_corners(x, y, z);
+                        views.iter().any(|view| outside_silhouette(view, &corners))
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut total_carved = 0;
+    for (x, y, z) in carved.into_iter().flatten() {
+        volume.set(x, y, z, Voxel::Carved);
+        total_carved += 1;
+    }
+
+    println!("Carved {} voxels outside the visual hull", total_carved);
+
+    Ok(())
+}
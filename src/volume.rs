@@ -1,5 +1,8 @@
+use crate::error::{CarvingError, Result};
 use nalgebra_glm as glm;
+use std::io::{self, Read, Write};
 
+/// An rgb color with each channel in `[0, 1]`
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Color {
     pub r: f32,
@@ -18,8 +21,23 @@ impl Color {
             b: v.z,
         }
     }
+    /// convert from 8 bit per channel rgb
+    pub fn from_rgb8(rgb: [u8; 3]) -> Color {
+        Color {
+            r: rgb[0] as f32 / 255.0,
+            g: rgb[1] as f32 / 255.0,
+            b: rgb[2] as f32 / 255.0,
+        }
+    }
+    /// convert to 8 bit per channel rgb, rounding to the nearest value
+    pub fn to_rgb8(&self) -> [u8; 3] {
+        let quantize = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        [quantize(self.r), quantize(self.g), quantize(self.b)]
+    }
 }
 
+/// The state of a single voxel. Voxels start out `Untouched` and are either
+/// `Carved` away or `Colored` once they pass a consistency check.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Voxel {
     Carved,
@@ -27,9 +45,62 @@ pub enum Voxel {
     Colored(Color),
 }
 
+/// One of the three axes of a volume
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+impl Axis {
+    /// the index of this axis's component in a vector
+    pub fn index(self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+}
+
+/// A fixed size set of bits packed into words
+#[derive(Debug, Clone)]
+struct BitSet {
+    words: Vec<u64>,
+}
+impl BitSet {
+    fn new(len: usize, value: bool) -> Self {
+        let fill = if value { u64::MAX } else { 0 };
+        BitSet {
+            words: vec![fill; len.div_ceil(64)],
+        }
+    }
+    fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+    fn set(&mut self, i: usize, value: bool) {
+        if value {
+            self.words[i / 64] |= 1 << (i % 64);
+        } else {
+            self.words[i / 64] &= !(1 << (i % 64));
+        }
+    }
+}
+
 /// A struct to represent a 3d volume of voxels.
+///
+/// Voxels are stored in flat arrays with `x` varying fastest, then `y`, then
+/// `z` (see `index`). Whether each voxel is still present and whether it has
+/// been colored are kept in bitsets, and colors in a separate array as
+/// `[u8; 3]`, so a voxel costs a little over 3 bytes. A `Colored` voxel
+/// therefore reads back, and is exported, with its color rounded to 8 bits per
+/// channel (see `Color::to_rgb8`).
 pub struct Volume {
-    pub data: Vec<Vec<Vec<Voxel>>>,
+    /// set for every voxel that hasn't been carved
+    occupied: BitSet,
+    /// set for every voxel that has a color
+    colored: BitSet,
+    colors: Vec<[u8; 3]>,
     pub voxel_size: f32,
     pub front_top_left: glm::Vec3,
     pub back_bottom_right: glm::Vec3,
@@ -38,9 +109,39 @@ pub struct Volume {
     pub depth: usize,
 }
 impl Volume {
-    /// create a new volume with bounding box defined by front_top_left and back_bottom_right, with
-    /// voxels of size voxel_size.
-    pub fn new(voxel_size: f32, front_top_left: glm::Vec3, back_bottom_right: glm::Vec3) -> Self {
+    /// the number of voxels along each axis of a volume with the given
+    /// bounding box and voxel size, as `(width, height, depth)`.
+    /// front_top_left must have the smallest x and the largest y and z
+    /// coordinates of the bounding box.
+    pub fn dimensions_for(
+        voxel_size: f32,
+        front_top_left: glm::Vec3,
+        back_bottom_right: glm::Vec3,
+    ) -> Result<(usize, usize, usize)> {
+        if !(voxel_size.is_finite() && voxel_size > 0.0) {
+            return Err(CarvingError::InvalidBoundingBox(format!(
+                "voxel size must be positive, got {}",
+                voxel_size
+            )));
+        }
+        if !(front_top_left.iter().all(|c| c.is_finite())
+            && back_bottom_right.iter().all(|c| c.is_finite()))
+        {
+            return Err(CarvingError::InvalidBoundingBox(
+                "corners must be finite".to_string(),
+            ));
+        }
+        if !(front_top_left.x < back_bottom_right.x
+            && front_top_left.y > back_bottom_right.y
+            && front_top_left.z > back_bottom_right.z)
+        {
+            return Err(CarvingError::InvalidBoundingBox(format!(
+                "front top left {:?} must have a smaller x and larger y and z than back bottom right {:?}",
+                front_top_left.as_slice(),
+                back_bottom_right.as_slice()
+            )));
+        }
+
         // Determine dimensions in # of voxels
         let width = ((back_bottom_right.x - front_top_left.x).abs() / voxel_size).ceil() as usize;
         let height = ((back_bottom_right.y - front_top_left.y).abs() / voxel_size).ceil() as usize;
@@ -51,51 +152,69 @@ impl Volume {
         let height = if height % 2 == 1 { height + 1 } else { height };
         let depth = if depth % 2 == 1 { depth + 1 } else { depth };
 
-        // Initialize a 3d vector of voxels
-        let mut cols = vec![];
-        for _ in 0..height {
-            let mut row = vec![];
-            for _ in 0..width {
-                let mut depth_line = vec![];
-                for _ in 0..depth {
-                    depth_line.push(Voxel::Untouched);
-                }
-                row.push(depth_line);
-            }
-            cols.push(row);
-        }
+        Ok((width, height, depth))
+    }
+    /// create a new volume with bounding box defined by front_top_left and back_bottom_right, with
+    /// voxels of size voxel_size. front_top_left must have the smallest x and the largest y and z
+    /// coordinates of the bounding box.
+    pub fn new(
+        voxel_size: f32,
+        front_top_left: glm::Vec3,
+        back_bottom_right: glm::Vec3,
+    ) -> Result<Self> {
+        let (width, height, depth) =
+            Self::dimensions_for(voxel_size, front_top_left, back_bottom_right)?;
 
-        debug_assert_eq!(cols.len(), height);
-        debug_assert_eq!(cols[0].len(), width);
-        debug_assert_eq!(cols[0][0].len(), depth);
+        // Every voxel starts out untouched
+        let len = width * height * depth;
 
         println!(
             "Created volume with dimensions: {}x{}x{}",
             width, height, depth
         );
 
-        Self {
-            data: cols,
+        Ok(Self {
+            occupied: BitSet::new(len, true),
+            colored: BitSet::new(len, false),
+            colors: vec![[0, 0, 0]; len],
             voxel_size,
             front_top_left,
             back_bottom_right,
-            width: width as usize,
-            height: height as usize,
-            depth: depth as usize,
-        }
+            width,
+            height,
+            depth,
+        })
     }
+    /// the scene-space position of the center of the voxel at the given indices
     pub fn voxel_to_position(&self, x: usize, y: usize, z: usize) -> glm::Vec3 {
         let x = self.front_top_left.x + (x as f32 * self.voxel_size) + (self.voxel_size / 2.0);
         let y = self.front_top_left.y - (y as f32 * self.voxel_size) - (self.voxel_size / 2.0);
         let z = self.front_top_left.z - (z as f32 * self.voxel_size) - (self.voxel_size / 2.0);
 
-        return glm::vec3(x, y, z);
+        glm::vec3(x, y, z)
+    }
+
+    /// the scene-space positions of the eight corners of the voxel at the given indices
+    pub fn voxel_corners(&self, x: usize, y: usize, z: usize) -> [glm::Vec3; 8] {
+        let center = self.voxel_to_position(x, y, z);
+        let s = self.voxel_size / 2.0;
+
+        [
+            center + glm::vec3(-s, -s, -s),
+            center + glm::vec3(s, -s, -s),
+            center + glm::vec3(-s, s, -s),
+            center + glm::vec3(s, s, -s),
+            center + glm::vec3(-s, -s, s),
+            center + glm::vec3(s, -s, s),
+            center + glm::vec3(-s, s, s),
+            center + glm::vec3(s, s, s),
+        ]
     }
 
     /// true if any of the six voxels surrounding this voxel are missing, false otherwise
-    pub fn voxel_visible(&self, x: usize, y: usize, z: usize) -> bool {
+    pub fn voxel_visible(&self, x: usize, y: usize, z: usize) -> Result<bool> {
         if x >= self.width || y >= self.height || z >= self.depth {
-            panic!("Voxel out of bounds");
+            return Err(CarvingError::VoxelOutOfBounds { x, y, z });
         }
 
         // If the voxel is on the edge of the volume, it's visible
@@ -106,11 +225,11 @@ impl Volume {
             || y == self.height - 1
             || z == self.depth - 1
         {
-            return true;
+            return Ok(true);
         }
 
         // enumerate all neighboring voxel coords and check if they are present
-        let coords = vec![
+        let coords = [
             (x - 1, y, z),
             (x + 1, y, z),
             (x, y - 1, z),
@@ -121,15 +240,186 @@ impl Volume {
 
         for (x, y, z) in coords {
             // If a neighboring voxel isn't present, then this voxel is visible
-            if self.data[y][x][z] == Voxel::Carved {
-                return true;
+            if self.is_carved(x, y, z) {
+                return Ok(true);
             }
         }
 
-        return false;
+        Ok(false)
+    }
+    /// the total number of voxels in the volume
+    pub fn len(&self) -> usize {
+        self.width * self.height * self.depth
+    }
+    /// true if the volume has no voxels at all
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// the position of the voxel at the given indices in the volume's flat
+    /// storage
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        debug_assert!(x < self.width && y < self.height && z < self.depth);
+        x + self.width * (y + self.height * z)
+    }
+    /// the voxel indices of the given position in the volume's flat storage,
+    /// the inverse of `index`
+    pub fn coords(&self, index: usize) -> (usize, usize, usize) {
+        let x = index % self.width;
+        let y = (index / self.width) % self.height;
+        let z = index / (self.width * self.height);
+        (x, y, z)
     }
-    /// Get a mutable reference to a voxel at the given indices
-    pub fn get_voxel(&mut self, x: usize, y: usize, z: usize) -> &mut Voxel {
-        &mut self.data[y][x][z]
+    /// Get the voxel at the given indices
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Voxel {
+        let i = self.index(x, y, z);
+        if !self.occupied.get(i) {
+            Voxel::Carved
+        } else if self.colored.get(i) {
+            Voxel::Colored(Color::from_rgb8(self.colors[i]))
+        } else {
+            Voxel::Untouched
+        }
+    }
+    /// Set the voxel at the given indices. Colors are quantized to 8 bits per
+    /// channel.
+    pub fn set(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+        let i = self.index(x, y, z);
+        match voxel {
+            Voxel::Carved => {
+                self.occupied.set(i, false);
+                self.colored.set(i, false);
+            }
+            Voxel::Untouched => {
+                self.occupied.set(i, true);
+                self.colored.set(i, false);
+            }
+            Voxel::Colored(color) => {
+                self.occupied.set(i, true);
+                self.colored.set(i, true);
+                self.colors[i] = color.to_rgb8();
+            }
+        }
+    }
+    /// true if the voxel at the given indices has been carved away
+    pub fn is_carved(&self, x: usize, y: usize, z: usize) -> bool {
+        !self.occupied.get(self.index(x, y, z))
+    }
+    /// iterate over the indices and state of every voxel, in storage order
+    pub fn voxels(&self) -> impl Iterator<Item = ((usize, usize, usize), Voxel)> + '_ {
+        (0..self.len()).map(move |i| {
+            let (x, y, z) = self.coords(i);
+            ((x, y, z), self.get(x, y, z))
+        })
+    }
+    /// the number of voxels along each axis
+    pub fn dimension(&self, axis: Axis) -> usize {
+        match axis {
+            Axis::X => self.width,
+            Axis::Y => self.height,
+            Axis::Z => self.depth,
+        }
+    }
+    /// iterate over the indices of every voxel in the plane perpendicular to
+    /// `axis` at position `a` along it. Voxels are visited in storage order so
+    /// that consecutive voxels are close together in memory.
+    pub fn slice(&self, axis: Axis, a: usize) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        let (outer, inner) = match axis {
+            Axis::X => (self.depth, self.height),
+            Axis::Y => (self.depth, self.width),
+            Axis::Z => (self.height, self.width),
+        };
+        (0..outer).flat_map(move |o| {
+            (0..inner).map(move |i| match axis {
+                Axis::X => (a, i, o),
+                Axis::Y => (i, a, o),
+                Axis::Z => (i, o, a),
+            })
+        })
+    }
+    /// write the voxel size, bounding box, dimensions and every voxel to
+    /// `out` in little endian, in the layout `read_from` expects. Only colored
+    /// voxels have their color written.
+    pub(crate) fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(&self.voxel_size.to_le_bytes())?;
+        for corner in [&self.front_top_left, &self.back_bottom_right] {
+            for c in corner.iter() {
+                out.write_all(&c.to_le_bytes())?;
+            }
+        }
+        for dimension in [self.width, self.height, self.depth] {
+            out.write_all(&(dimension as u64).to_le_bytes())?;
+        }
+        for bits in [&self.occupied, &self.colored] {
+            for word in &bits.words {
+                out.write_all(&word.to_le_bytes())?;
+            }
+        }
+        for i in 0..self.len() {
+            if self.colored.get(i) {
+                out.write_all(&self.colors[i])?;
+            }
+        }
+        Ok(())
+    }
+    /// read a volume written by `write_to`
+    pub(crate) fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        fn read<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+            let mut bytes = [0; N];
+            input.read_exact(&mut bytes)?;
+            Ok(bytes)
+        }
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let voxel_size = f32::from_le_bytes(read(input)?);
+        let mut corners = [glm::vec3(0.0, 0.0, 0.0); 2];
+        for corner in &mut corners {
+            for c in corner.iter_mut() {
+                *c = f32::from_le_bytes(read(input)?);
+            }
+        }
+        let [front_top_left, back_bottom_right] = corners;
+        let mut dimensions = [0; 3];
+        for dimension in &mut dimensions {
+            *dimension = u64::from_le_bytes(read(input)?) as usize;
+        }
+
+        // The dimensions follow from the bounding box, so if they don't match
+        // the file is damaged
+        let expected = Self::dimensions_for(voxel_size, front_top_left, back_bottom_right)
+            .map_err(|e| invalid(e.to_string()))?;
+        let [width, height, depth] = dimensions;
+        if (width, height, depth) != expected {
+            return Err(invalid(format!(
+                "dimensions {}x{}x{} don't match the bounding box",
+                width, height, depth
+            )));
+        }
+
+        let len = width * height * depth;
+        let mut bitsets = [BitSet::new(len, false), BitSet::new(len, false)];
+        for bits in &mut bitsets {
+            for word in &mut bits.words {
+                *word = u64::from_le_bytes(read(input)?);
+            }
+        }
+        let [occupied, colored] = bitsets;
+        let mut colors = vec![[0, 0, 0]; len];
+        for (i, color) in colors.iter_mut().enumerate() {
+            if colored.get(i) {
+                *color = read(input)?;
+            }
+        }
+
+        Ok(Self {
+            occupied,
+            colored,
+            colors,
+            voxel_size,
+            front_top_left,
+            back_bottom_right,
+            width,
+            height,
+            depth,
+        })
     }
 }